@@ -36,7 +36,8 @@ use proc_macro_error2::{abort_call_site, proc_macro_error};
 use quote::{format_ident, quote};
 use syn::{
     parse_macro_input, parse_quote, punctuated::Punctuated, token::Comma, Data, DataEnum,
-    DataStruct, DeriveInput, Field, Fields, FieldsNamed, FieldsUnnamed, GenericParam, Type,
+    DataStruct, DeriveInput, Field, Fields, FieldsNamed, FieldsUnnamed, GenericParam,
+    LifetimeParam, Type,
 };
 
 fn use_matrix_pickle() -> TokenStream2 {
@@ -75,7 +76,7 @@ pub fn derive_encode(input: TokenStream) -> TokenStream {
 
             quote! {
                 impl #impl_generics #matrix_pickle::Encode for #name #ty_generics #where_clause {
-                    fn encode(&self, writer: &mut impl std::io::Write) -> Result<usize, #matrix_pickle::EncodeError> {
+                    fn encode(&self, writer: &mut impl #matrix_pickle::io::Write) -> Result<usize, #matrix_pickle::EncodeError> {
                         let mut ret = 0;
 
                         #(ret += self.#idents.encode(writer)?;)*
@@ -93,7 +94,7 @@ pub fn derive_encode(input: TokenStream) -> TokenStream {
 
             quote! {
                 impl #impl_generics #matrix_pickle::Encode for #name #ty_generics #where_clause {
-                    fn encode(&self, writer: &mut impl std::io::Write) -> Result<usize, #matrix_pickle::EncodeError> {
+                    fn encode(&self, writer: &mut impl #matrix_pickle::io::Write) -> Result<usize, #matrix_pickle::EncodeError> {
                         let mut ret = 0;
 
                         #(ret += self.#i.encode(writer)?;)*
@@ -109,7 +110,7 @@ pub fn derive_encode(input: TokenStream) -> TokenStream {
 
             quote! {
                 impl #impl_generics #matrix_pickle::Encode for #name #ty_generics #where_clause {
-                    fn encode(&self, writer: &mut impl std::io::Write) -> Result<usize, #matrix_pickle::EncodeError> {
+                    fn encode(&self, writer: &mut impl #matrix_pickle::io::Write) -> Result<usize, #matrix_pickle::EncodeError> {
                         let mut ret = 0;
 
                         match self {
@@ -147,6 +148,19 @@ fn check_if_boxed(fields: &Punctuated<Field, Comma>) {
     }
 }
 
+/// The names of the fields as they appear in the path of a decoding error,
+/// fields of tuple structs are named after their index.
+fn field_names(fields: &Punctuated<Field, Comma>) -> Vec<String> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, f)| match &f.ident {
+            Some(ident) => ident.to_string(),
+            None => i.to_string(),
+        })
+        .collect()
+}
+
 /// Derive an `Decode` implementation for a struct or enum.
 #[proc_macro_error]
 #[proc_macro_derive(Decode, attributes(secret))]
@@ -158,28 +172,226 @@ pub fn derive_decode(input: TokenStream) -> TokenStream {
 
     for param in &mut input.generics.params {
         if let GenericParam::Type(ref mut type_param) = *param {
-            type_param.bounds.push(parse_quote!(#matrix_pickle::Encode));
+            type_param.bounds.push(parse_quote!(#matrix_pickle::Decode));
         }
     }
 
     let generics = input.generics;
     let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let type_name = name.to_string();
 
-    match input.data {
+    // Structs have a fixed encoded size if all their fields have one, which
+    // allows them to be used as elements of a `LazySeq`.
+    let encoded_size = match &input.data {
+        Data::Struct(DataStruct { fields, .. }) => {
+            let field_types = fields.iter().map(|f| &f.ty);
+
+            quote! {
+                const ENCODED_SIZE: Option<usize> = #matrix_pickle::sum_encoded_sizes(&[
+                    #(<#field_types as #matrix_pickle::Decode>::ENCODED_SIZE),*
+                ]);
+            }
+        }
+        _ => quote! {},
+    };
+
+    let (decode_with, decode_into) = match input.data {
         Data::Struct(DataStruct {
             fields: Fields::Named(FieldsNamed { named, .. }),
             ..
         }) => {
             check_if_boxed(&named);
 
+            let names: Vec<_> = named.iter().map(|f| &f.ident).collect();
+            let field_names = field_names(&named);
+            let field_types: Vec<_> = named.iter().map(|f| &f.ty).collect();
+
+            let decode_with = quote! {
+                Ok(Self {
+                    #(#names: <#field_types as #matrix_pickle::Decode>::decode_with(decoder)
+                        .map_err(|e| e.in_field(#type_name, #field_names))?),*
+                })
+            };
+            let decode_into = quote! {
+                #(<#field_types as #matrix_pickle::Decode>::decode_into_with(&mut self.#names, decoder)
+                    .map_err(|e| e.in_field(#type_name, #field_names))?;)*
+
+                Ok(())
+            };
+
+            (decode_with, decode_into)
+        }
+        Data::Struct(DataStruct {
+            fields: Fields::Unnamed(FieldsUnnamed { unnamed, .. }),
+            ..
+        }) => {
+            check_if_boxed(&unnamed);
+
+            let i: Vec<_> = (0..unnamed.len()).map(syn::Index::from).collect();
+            let field_names = field_names(&unnamed);
+            let field_types: Vec<_> = unnamed.iter().map(|f| &f.ty).collect();
+
+            let decode_with = quote! {
+                Ok(Self (
+                    #(<#field_types as #matrix_pickle::Decode>::decode_with(decoder)
+                        .map_err(|e| e.in_field(#type_name, #field_names))?),*
+                ))
+            };
+            let decode_into = quote! {
+                #(<#field_types as #matrix_pickle::Decode>::decode_into_with(&mut self.#i, decoder)
+                    .map_err(|e| e.in_field(#type_name, #field_names))?;)*
+
+                Ok(())
+            };
+
+            (decode_with, decode_into)
+        }
+        Data::Enum(DataEnum { variants, .. }) => {
+            let names: Vec<_> = variants.iter().map(|v| &v.ident).collect();
+            let variant_names: Vec<_> = variants.iter().map(|v| v.ident.to_string()).collect();
+            let variant_count = variants.len() as u16;
+            let numbers: Vec<u8> = (0u8..variants
+                .len()
+                .try_into()
+                .expect("Only enums with up to 256 elements are supported"))
+                .collect();
+
+            let unknown_variant = quote! {
+                #matrix_pickle::DecodeError::UnknownEnumVariant {
+                    type_name: #type_name,
+                    variant,
+                    variants: 0..#variant_count,
+                    offset,
+                }
+            };
+
+            let decode_with = quote! {
+                let offset = decoder.position();
+                let variant = <u8 as #matrix_pickle::Decode>::decode_with(decoder)?;
+
+                match variant {
+                    #(#numbers => {
+                        let x = #matrix_pickle::Decode::decode_with(decoder)
+                            .map_err(|e| e.in_variant(#type_name, #variant_names))?;
+                        Ok(Self::#names(x))
+                    }),*
+
+                    _ => Err(#unknown_variant)
+                }
+            };
+
+            // If the value already holds the decoded variant, its value is
+            // decoded in place, otherwise the value gets replaced.
+            let decode_into = quote! {
+                let offset = decoder.position();
+                let variant = <u8 as #matrix_pickle::Decode>::decode_with(decoder)?;
+
+                match (variant, self) {
+                    #(
+                        (#numbers, Self::#names(x)) => {
+                            #matrix_pickle::Decode::decode_into_with(x, decoder)
+                                .map_err(|e| e.in_variant(#type_name, #variant_names))?;
+                        }
+                        (#numbers, this) => {
+                            let x = #matrix_pickle::Decode::decode_with(decoder)
+                                .map_err(|e| e.in_variant(#type_name, #variant_names))?;
+                            *this = Self::#names(x);
+                        }
+                    )*
+
+                    _ => return Err(#unknown_variant),
+                }
+
+                Ok(())
+            };
+
+            (decode_with, decode_into)
+        }
+        _ => abort_call_site!("`#[derive(Decode)` only supports structs and enums"),
+    };
+
+    quote! {
+        impl #impl_generics #matrix_pickle::Decode for #name #ty_generics #where_clause {
+            #encoded_size
+
+            fn decode(reader: &mut impl #matrix_pickle::io::Read) -> Result<Self, #matrix_pickle::DecodeError> {
+                #matrix_pickle::Decoder::new(reader).decode()
+            }
+
+            fn decode_with(
+                decoder: &mut #matrix_pickle::Decoder<impl #matrix_pickle::io::Read>,
+            ) -> Result<Self, #matrix_pickle::DecodeError> {
+                decoder.nested(|decoder| { #decode_with })
+            }
+
+            fn decode_into_with(
+                &mut self,
+                decoder: &mut #matrix_pickle::Decoder<impl #matrix_pickle::io::Read>,
+            ) -> Result<(), #matrix_pickle::DecodeError> {
+                decoder.nested(|decoder| { #decode_into })
+            }
+        }
+    }
+    .into()
+}
+
+/// Derive a `DecodeBorrowed` implementation for a struct or enum.
+///
+/// The struct or enum may have lifetime parameters, the fields using them
+/// will borrow from the buffer the value is decoded from.
+///
+/// This can't be combined with `#[derive(Decode)]`, every type implementing
+/// `Decode` already implements `DecodeBorrowed`, so the two implementations
+/// would conflict. Types which don't borrow anything should derive `Decode`.
+#[proc_macro_error]
+#[proc_macro_derive(DecodeBorrowed)]
+pub fn derive_decode_borrowed(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+
+    let matrix_pickle = use_matrix_pickle();
+
+    // The `'de` lifetime of the input buffer needs to outlive every lifetime
+    // of the type we're decoding.
+    let mut generics = input.generics.clone();
+    let mut de_lifetime: LifetimeParam = parse_quote!('de);
+    de_lifetime.bounds = generics.lifetimes().map(|l| l.lifetime.clone()).collect();
+
+    for param in &mut generics.params {
+        if let GenericParam::Type(ref mut type_param) = *param {
+            type_param
+                .bounds
+                .push(parse_quote!(#matrix_pickle::DecodeBorrowed<'de>));
+        }
+    }
+
+    generics
+        .params
+        .insert(0, GenericParam::Lifetime(de_lifetime));
+
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+    let type_name = name.to_string();
+
+    match input.data {
+        Data::Struct(DataStruct {
+            fields: Fields::Named(FieldsNamed { named, .. }),
+            ..
+        }) => {
             let names = named.iter().map(|f| &f.ident);
+            let field_names = field_names(&named);
             let field_types = named.iter().map(|f| &f.ty);
 
             quote! {
-                impl #impl_generics #matrix_pickle::Decode for #name #ty_generics #where_clause {
-                    fn decode(reader: &mut impl std::io::Read) -> Result<Self, #matrix_pickle::DecodeError> {
-                        Ok(Self {
-                            #(#names: <#field_types>::decode(reader)?),*
+                impl #impl_generics #matrix_pickle::DecodeBorrowed<'de> for #name #ty_generics #where_clause {
+                    fn decode_borrowed_with(
+                        decoder: &mut #matrix_pickle::Decoder<&'de [u8]>,
+                    ) -> Result<Self, #matrix_pickle::DecodeError> {
+                        decoder.nested(|decoder| {
+                            Ok(Self {
+                                #(#names: <#field_types as #matrix_pickle::DecodeBorrowed<'de>>::decode_borrowed_with(decoder)
+                                    .map_err(|e| e.in_field(#type_name, #field_names))?),*
+                            })
                         })
                     }
                 }
@@ -189,41 +401,244 @@ pub fn derive_decode(input: TokenStream) -> TokenStream {
             fields: Fields::Unnamed(FieldsUnnamed { unnamed, .. }),
             ..
         }) => {
-            check_if_boxed(&unnamed);
-
+            let field_names = field_names(&unnamed);
             let field_types = unnamed.iter().map(|f| &f.ty);
 
             quote! {
-                impl #impl_generics #matrix_pickle::Decode for #name #ty_generics #where_clause {
-                    fn decode(reader: &mut impl std::io::Read) -> Result<Self, #matrix_pickle::DecodeError> {
-                        Ok(Self (
-                            #(<#field_types>::decode(reader)?),*
-                        ))
+                impl #impl_generics #matrix_pickle::DecodeBorrowed<'de> for #name #ty_generics #where_clause {
+                    fn decode_borrowed_with(
+                        decoder: &mut #matrix_pickle::Decoder<&'de [u8]>,
+                    ) -> Result<Self, #matrix_pickle::DecodeError> {
+                        decoder.nested(|decoder| {
+                            Ok(Self (
+                                #(<#field_types as #matrix_pickle::DecodeBorrowed<'de>>::decode_borrowed_with(decoder)
+                                    .map_err(|e| e.in_field(#type_name, #field_names))?),*
+                            ))
+                        })
                     }
                 }
             }
         }
         Data::Enum(DataEnum { variants, .. }) => {
             let names = variants.iter().map(|v| &v.ident);
+            let variant_names = variants.iter().map(|v| v.ident.to_string());
+            let variant_count = variants.len() as u16;
             let numbers = 0u8..variants.len().try_into().expect("Only enums with up to 256 elements are supported");
 
             quote! {
-                impl #impl_generics #matrix_pickle::Decode for #name #ty_generics #where_clause {
-                    fn decode(reader: &mut impl std::io::Read) -> Result<Self, #matrix_pickle::DecodeError> {
-                        let variant = u8::decode(reader)?;
-
-                        match variant {
-                            #(#numbers => {
-                                let x = Decode::decode(reader)?;
-                                Ok(Self::#names(x))
-                            }),*
+                impl #impl_generics #matrix_pickle::DecodeBorrowed<'de> for #name #ty_generics #where_clause {
+                    fn decode_borrowed_with(
+                        decoder: &mut #matrix_pickle::Decoder<&'de [u8]>,
+                    ) -> Result<Self, #matrix_pickle::DecodeError> {
+                        decoder.nested(|decoder| {
+                            let offset = decoder.position();
+                            let variant = <u8 as #matrix_pickle::Decode>::decode_with(decoder)?;
 
-                            _ => Err(#matrix_pickle::DecodeError::UnknownEnumVariant(variant))
-                        }
+                            match variant {
+                                #(#numbers => {
+                                    let x = #matrix_pickle::DecodeBorrowed::decode_borrowed_with(decoder)
+                                        .map_err(|e| e.in_variant(#type_name, #variant_names))?;
+                                    Ok(Self::#names(x))
+                                }),*
+
+                                _ => Err(#matrix_pickle::DecodeError::UnknownEnumVariant {
+                                    type_name: #type_name,
+                                    variant,
+                                    variants: 0..#variant_count,
+                                    offset,
+                                })
+                            }
+                        })
                     }
                 }
             }
         }
-        _ => abort_call_site!("`#[derive(Encode)` only supports non-tuple structs"),
+        _ => abort_call_site!("`#[derive(DecodeBorrowed)` only supports structs and enums"),
     }.into()
 }
+
+/// Derive an `AsyncDecode` implementation for a struct or enum.
+///
+/// The type needs to implement `Decode` as well, the fields are decoded using
+/// their own `AsyncDecode` implementations.
+#[proc_macro_error]
+#[proc_macro_derive(AsyncDecode)]
+pub fn derive_async_decode(input: TokenStream) -> TokenStream {
+    let mut input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+
+    let matrix_pickle = use_matrix_pickle();
+
+    for param in &mut input.generics.params {
+        if let GenericParam::Type(ref mut type_param) = *param {
+            type_param
+                .bounds
+                .push(parse_quote!(#matrix_pickle::AsyncDecode));
+        }
+    }
+
+    let generics = input.generics;
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let type_name = name.to_string();
+
+    let decode = match input.data {
+        Data::Struct(DataStruct {
+            fields: Fields::Named(FieldsNamed { named, .. }),
+            ..
+        }) => {
+            let names = named.iter().map(|f| &f.ident);
+            let field_names = field_names(&named);
+            let field_types = named.iter().map(|f| &f.ty);
+
+            quote! {
+                Ok(Self {
+                    #(#names: decoder.decode::<#field_types>().await
+                        .map_err(|e| e.in_field(#type_name, #field_names))?),*
+                })
+            }
+        }
+        Data::Struct(DataStruct {
+            fields: Fields::Unnamed(FieldsUnnamed { unnamed, .. }),
+            ..
+        }) => {
+            let field_names = field_names(&unnamed);
+            let field_types = unnamed.iter().map(|f| &f.ty);
+
+            quote! {
+                Ok(Self (
+                    #(decoder.decode::<#field_types>().await
+                        .map_err(|e| e.in_field(#type_name, #field_names))?),*
+                ))
+            }
+        }
+        Data::Enum(DataEnum { variants, .. }) => {
+            let names = variants.iter().map(|v| &v.ident);
+            let variant_names = variants.iter().map(|v| v.ident.to_string());
+            let variant_count = variants.len() as u16;
+            let numbers = 0u8..variants
+                .len()
+                .try_into()
+                .expect("Only enums with up to 256 elements are supported");
+
+            quote! {
+                let offset = decoder.position();
+                let variant = decoder.decode::<u8>().await?;
+
+                match variant {
+                    #(#numbers => {
+                        let x = decoder.decode().await
+                            .map_err(|e| e.in_variant(#type_name, #variant_names))?;
+                        Ok(Self::#names(x))
+                    }),*
+
+                    _ => Err(#matrix_pickle::DecodeError::UnknownEnumVariant {
+                        type_name: #type_name,
+                        variant,
+                        variants: 0..#variant_count,
+                        offset,
+                    })
+                }
+            }
+        }
+        _ => abort_call_site!("`#[derive(AsyncDecode)` only supports structs and enums"),
+    };
+
+    quote! {
+        impl #impl_generics #matrix_pickle::AsyncDecode for #name #ty_generics #where_clause {
+            fn decode_async_with<'a, R: #matrix_pickle::AsyncReader + 'a>(
+                decoder: &'a mut #matrix_pickle::AsyncDecoder<R>,
+            ) -> #matrix_pickle::DecodeFuture<'a, Self> {
+                Box::pin(async move {
+                    decoder.enter()?;
+                    let result = async { #decode }.await;
+                    decoder.leave();
+
+                    result
+                })
+            }
+        }
+    }
+    .into()
+}
+
+/// Derive an `AsyncEncode` implementation for a struct or enum.
+///
+/// The type needs to implement `Encode` as well, the fields are encoded using
+/// their own `AsyncEncode` implementations.
+#[proc_macro_error]
+#[proc_macro_derive(AsyncEncode)]
+pub fn derive_async_encode(input: TokenStream) -> TokenStream {
+    let mut input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+
+    let matrix_pickle = use_matrix_pickle();
+
+    for param in &mut input.generics.params {
+        if let GenericParam::Type(ref mut type_param) = *param {
+            type_param
+                .bounds
+                .push(parse_quote!(#matrix_pickle::AsyncEncode));
+        }
+    }
+
+    let generics = input.generics;
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let encode = match input.data {
+        Data::Struct(DataStruct {
+            fields: Fields::Named(FieldsNamed { named, .. }),
+            ..
+        }) => {
+            let idents = named.iter().map(|f| &f.ident);
+
+            quote! {
+                #(ret += encoder.encode(&self.#idents).await?;)*
+            }
+        }
+        Data::Struct(DataStruct {
+            fields: Fields::Unnamed(FieldsUnnamed { unnamed, .. }),
+            ..
+        }) => {
+            let i = (0..unnamed.len()).map(syn::Index::from);
+
+            quote! {
+                #(ret += encoder.encode(&self.#i).await?;)*
+            }
+        }
+        Data::Enum(DataEnum { variants, .. }) => {
+            let names = variants.iter().map(|v| &v.ident);
+            let numbers = 0u8..variants
+                .len()
+                .try_into()
+                .expect("Only enums with up to 256 elements are supported");
+
+            quote! {
+                match self {
+                    #(#name::#names(v) => {
+                        ret += encoder.encode(&#numbers).await?;
+                        ret += encoder.encode(v).await?;
+                    }),*
+                }
+            }
+        }
+        _ => abort_call_site!("`#[derive(AsyncEncode)` only supports structs and enums"),
+    };
+
+    quote! {
+        impl #impl_generics #matrix_pickle::AsyncEncode for #name #ty_generics #where_clause {
+            fn encode_async_with<'a, W: #matrix_pickle::AsyncWriter + 'a>(
+                &'a self,
+                encoder: &'a mut #matrix_pickle::AsyncEncoder<W>,
+            ) -> #matrix_pickle::EncodeFuture<'a> {
+                Box::pin(async move {
+                    let mut ret = 0;
+
+                    #encode
+
+                    Ok(ret)
+                })
+            }
+        }
+    }
+    .into()
+}
@@ -0,0 +1,1249 @@
+// Copyright 2022 Damir Jelić
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    future::{poll_fn, Future},
+    hash::{BuildHasher, Hash},
+    io::ErrorKind,
+    mem::size_of,
+    num::{
+        NonZeroI128, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroU128, NonZeroU16,
+        NonZeroU32, NonZeroU64, NonZeroU8, NonZeroUsize,
+    },
+    ops::Deref,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
+};
+#[cfg(feature = "sans-io")]
+use std::{
+    sync::{Mutex, MutexGuard, PoisonError},
+    task::{Wake, Waker},
+};
+
+use crate::{
+    decode::{array_length_mismatch, check_sorted},
+    decoder::DecodeState,
+    Decode, DecodeError, DecodeOptions, Decoder, Encode, EncodeError, StrictFloat,
+    MAX_ARRAY_LENGTH,
+};
+use zeroize::{Zeroize, Zeroizing};
+
+/// The number of bytes of a sequence with fixed-size elements which are read
+/// at once, before the elements are decoded.
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// The future returned by [`AsyncDecode`] methods.
+pub type DecodeFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, DecodeError>> + Send + 'a>>;
+
+/// The future returned by [`AsyncEncode`] methods.
+pub type EncodeFuture<'a> = Pin<Box<dyn Future<Output = Result<usize, EncodeError>> + Send + 'a>>;
+
+/// Poll the future once, using a waker which does nothing.
+///
+/// This is used by the [`PushDecoder`] and the [`PullEncoder`], which poll
+/// their future again themselves once they can make progress.
+///
+/// [`PushDecoder`]: crate::PushDecoder
+/// [`PullEncoder`]: crate::PullEncoder
+#[cfg(feature = "sans-io")]
+pub(crate) fn poll_now<T>(future: Pin<&mut (dyn Future<Output = T> + Send + '_)>) -> Poll<T> {
+    let waker = Waker::from(Arc::new(NoopWaker));
+
+    future.poll(&mut Context::from_waker(&waker))
+}
+
+#[cfg(feature = "sans-io")]
+struct NoopWaker;
+
+#[cfg(feature = "sans-io")]
+impl Wake for NoopWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+/// Lock the mutex, ignoring poisoning since the guarded buffers stay
+/// consistent even if a panic happened while they were locked.
+#[cfg(feature = "sans-io")]
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A queue of bytes which may contain secrets, e.g. encoded values or input
+/// which hasn't been decoded yet.
+///
+/// Bytes are zeroized as soon as they are consumed, and the whole buffer once
+/// it's dropped. Growing the buffer moves the bytes into a new allocation and
+/// zeroizes the old one, so no copies are left behind.
+#[derive(Default)]
+pub(crate) struct SecretBuffer {
+    bytes: Zeroizing<Vec<u8>>,
+    /// The position of the first byte which hasn't been consumed yet.
+    position: usize,
+}
+
+impl SecretBuffer {
+    #[cfg(feature = "sans-io")]
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self {
+            bytes: Zeroizing::new(Vec::with_capacity(capacity)),
+            position: 0,
+        }
+    }
+
+    /// Append the given bytes to the ones which haven't been consumed yet.
+    pub(crate) fn extend_from_slice(&mut self, bytes: &[u8]) {
+        self.compact();
+
+        let needed = self.bytes.len() + bytes.len();
+
+        if needed > self.bytes.capacity() {
+            let capacity = needed.max(self.bytes.capacity() * 2);
+            let mut grown = Zeroizing::new(Vec::with_capacity(capacity));
+            grown.extend_from_slice(&self.bytes);
+
+            // The old allocation is zeroized once it's dropped here.
+            self.bytes = grown;
+        }
+
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    /// Consume the given number of bytes from the front, zeroizing them.
+    #[cfg(feature = "sans-io")]
+    pub(crate) fn consume(&mut self, count: usize) {
+        let end = self.position + count;
+
+        self.bytes[self.position..end].zeroize();
+        self.position = end;
+    }
+
+    /// Zeroize and remove all bytes.
+    pub(crate) fn clear(&mut self) {
+        self.bytes.zeroize();
+        self.position = 0;
+    }
+
+    /// Get back the bytes which haven't been consumed yet.
+    #[cfg(feature = "sans-io")]
+    pub(crate) fn into_unconsumed(mut self) -> Zeroizing<Vec<u8>> {
+        self.compact();
+
+        std::mem::take(&mut self.bytes)
+    }
+
+    /// Move the bytes which haven't been consumed to the start of the
+    /// buffer, and zeroize the bytes that are left behind.
+    fn compact(&mut self) {
+        if self.position > 0 {
+            let remaining = self.bytes.len() - self.position;
+
+            self.bytes.copy_within(self.position.., 0);
+            self.bytes[remaining..].zeroize();
+            self.bytes.truncate(remaining);
+            self.position = 0;
+        }
+    }
+}
+
+impl std::fmt::Debug for SecretBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SecretBuffer")
+            .field("len", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Deref for SecretBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes[self.position..]
+    }
+}
+
+impl std::io::Write for SecretBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.extend_from_slice(buf);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A source of bytes for an [`AsyncDecoder`].
+///
+/// This is implemented by [`TokioReader`] and [`FuturesReader`], which wrap
+/// the `AsyncRead` traits of `tokio` and `futures-io` respectively.
+pub trait AsyncReader: Send + Unpin {
+    /// Attempt to read bytes into the given buffer, returning the number of
+    /// bytes that were read, zero bytes signal the end of the input.
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<std::io::Result<usize>>;
+}
+
+/// An [`AsyncReader`] wrapping a `tokio::io::AsyncRead` implementation.
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct TokioReader<R>(pub R);
+
+#[cfg(feature = "tokio")]
+impl<R: tokio::io::AsyncRead + Send + Unpin> AsyncReader for TokioReader<R> {
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<std::io::Result<usize>> {
+        let mut buf = tokio::io::ReadBuf::new(buf);
+
+        match Pin::new(&mut self.0).poll_read(cx, &mut buf) {
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(buf.filled().len())),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// An [`AsyncReader`] wrapping a `futures_io::AsyncRead` implementation.
+#[cfg(feature = "futures-io")]
+#[derive(Debug)]
+pub struct FuturesReader<R>(pub R);
+
+#[cfg(feature = "futures-io")]
+impl<R: futures_io::AsyncRead + Send + Unpin> AsyncReader for FuturesReader<R> {
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+/// A sink of bytes for an [`AsyncEncoder`].
+///
+/// This is implemented by [`TokioWriter`] and [`FuturesWriter`], which wrap
+/// the `AsyncWrite` traits of `tokio` and `futures-io` respectively.
+pub trait AsyncWriter: Send + Unpin {
+    /// Attempt to write bytes from the given buffer, returning the number of
+    /// bytes that were written.
+    fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>>;
+}
+
+/// An [`AsyncWriter`] wrapping a `tokio::io::AsyncWrite` implementation.
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct TokioWriter<W>(pub W);
+
+#[cfg(feature = "tokio")]
+impl<W: tokio::io::AsyncWrite + Send + Unpin> AsyncWriter for TokioWriter<W> {
+    fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+}
+
+/// An [`AsyncWriter`] wrapping a `futures_io::AsyncWrite` implementation.
+#[cfg(feature = "futures-io")]
+#[derive(Debug)]
+pub struct FuturesWriter<W>(pub W);
+
+#[cfg(feature = "futures-io")]
+impl<W: futures_io::AsyncWrite + Send + Unpin> AsyncWriter for FuturesWriter<W> {
+    fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+}
+
+/// The asynchronous counterpart of the [`Decoder`], which enforces the same
+/// [`DecodeOptions`] limits.
+///
+/// Values with a fixed encoded size, like integers or byte arrays, are read
+/// in one go and then decoded synchronously.
+#[derive(Debug)]
+pub struct AsyncDecoder<R> {
+    reader: R,
+    state: DecodeState,
+}
+
+impl<R: AsyncReader> AsyncDecoder<R> {
+    /// Create a new `AsyncDecoder` using the default [`DecodeOptions`].
+    pub fn new(reader: R) -> Self {
+        Self::with_options(reader, DecodeOptions::default())
+    }
+
+    /// Create a new `AsyncDecoder` which enforces the given [`DecodeOptions`].
+    pub fn with_options(reader: R, options: DecodeOptions) -> Self {
+        Self {
+            reader,
+            state: DecodeState::new(options),
+        }
+    }
+
+    /// Try to read and decode a value.
+    ///
+    /// If decoding fails, the offset of the error is counted from the start
+    /// of the input of this `AsyncDecoder`.
+    pub async fn decode<T: AsyncDecode>(&mut self) -> Result<T, DecodeError> {
+        match T::ENCODED_SIZE {
+            Some(size) => self.decode_buffered(size, |decoder| decoder.decode()).await,
+            None => T::decode_async_with(self).await,
+        }
+    }
+
+    /// The number of bytes this `AsyncDecoder` has read so far.
+    pub fn position(&self) -> usize {
+        self.state.position
+    }
+
+    /// The options this `AsyncDecoder` enforces.
+    pub fn options(&self) -> &DecodeOptions {
+        &self.state.options
+    }
+
+    /// Get back the reader this `AsyncDecoder` wraps.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Read exactly enough bytes to fill the given buffer.
+    pub async fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), DecodeError> {
+        let mut filled = 0;
+
+        while filled < buf.len() {
+            let reader = &mut self.reader;
+            let unfilled = &mut buf[filled..];
+
+            match poll_fn(|cx| reader.poll_read(cx, unfilled)).await {
+                Ok(0) => {
+                    return Err(DecodeError::IO {
+                        source: ErrorKind::UnexpectedEof.into(),
+                        offset: self.state.position,
+                    })
+                }
+                Ok(read) => {
+                    filled += read;
+                    self.state.position += read;
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(source) => {
+                    return Err(DecodeError::IO {
+                        source,
+                        offset: self.state.position,
+                    })
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Decode the length prefix of an array, checking it against the maximum
+    /// array length.
+    pub async fn decode_length(&mut self) -> Result<usize, DecodeError> {
+        let offset = self.position();
+        let length = self.decode::<usize>().await?;
+
+        self.state.check_length(length, offset)
+    }
+
+    /// Claim space for `length` values of type `T` from the allocation
+    /// budget.
+    ///
+    /// This should be called before allocating memory whose size depends on
+    /// the decoded input. The `offset` is the position of the value the space
+    /// is claimed for, usually the start of its length prefix, and is used as
+    /// the offset of the error.
+    pub fn claim_allocation<T>(&mut self, length: usize, offset: usize) -> Result<(), DecodeError> {
+        self.state.claim_allocation::<T>(length, offset)
+    }
+
+    /// Go one nesting level deeper, checking the maximum nesting depth.
+    ///
+    /// Every successful call needs to be followed by a call to
+    /// [`AsyncDecoder::leave()`] once the nested value has been decoded.
+    pub fn enter(&mut self) -> Result<(), DecodeError> {
+        self.state.enter()
+    }
+
+    /// Go back up one nesting level after a call to [`AsyncDecoder::enter()`].
+    pub fn leave(&mut self) {
+        self.state.leave()
+    }
+
+    /// Read `size` bytes and decode them synchronously, using a [`Decoder`]
+    /// which continues where this `AsyncDecoder` left off.
+    ///
+    /// The size comes from `Decode::ENCODED_SIZE`, which hand-written
+    /// implementations may get wrong. A value which needs more bytes fails
+    /// with an IO error once the buffer runs out, and one which leaves some
+    /// of the bytes unread fails with [`DecodeError::TrailingBytes`].
+    async fn decode_buffered<T>(
+        &mut self,
+        size: usize,
+        decode: impl FnOnce(&mut Decoder<&[u8]>) -> Result<T, DecodeError>,
+    ) -> Result<T, DecodeError> {
+        let state = self.state.clone();
+
+        // The buffer may contain secrets, so it's zeroized once it's dropped,
+        // even if the future is dropped before the value is complete.
+        let mut buffer = Zeroizing::new(vec![0u8; size]);
+        self.read_exact(&mut buffer).await?;
+
+        let mut decoder = Decoder::from_state(buffer.as_slice(), state);
+        let value = decode(&mut decoder);
+        let offset = decoder.position();
+        let remaining;
+        (remaining, self.state) = decoder.into_parts();
+
+        let value = value?;
+
+        if remaining.is_empty() {
+            Ok(value)
+        } else {
+            Err(DecodeError::TrailingBytes {
+                remaining: remaining.len(),
+                offset,
+            })
+        }
+    }
+
+    /// Decode `length` consecutive values, without a length prefix.
+    async fn decode_elements<T: AsyncDecode>(
+        &mut self,
+        length: usize,
+    ) -> Result<Vec<T>, DecodeError> {
+        let mut elements = Vec::with_capacity(length);
+
+        if let Some(size) = T::ENCODED_SIZE {
+            // Read the elements in chunks, so a bogus length doesn't make us
+            // allocate a large buffer before the bytes arrive.
+            let chunk_length = (CHUNK_SIZE / size.max(1)).max(1);
+
+            while elements.len() < length {
+                let start = elements.len();
+                let count = chunk_length.min(length - start);
+
+                let chunk = self
+                    .decode_buffered(count * size, |decoder| T::decode_elements(decoder, count))
+                    .await
+                    .map_err(|e| e.shift_index(start))?;
+
+                elements.extend(chunk);
+            }
+        } else {
+            for index in 0..length {
+                elements.push(self.decode().await.map_err(|e| e.at_index(index))?);
+            }
+        }
+
+        Ok(elements)
+    }
+}
+
+/// A trait for asynchronously decoding values that were encoded using the
+/// `matrix-pickle` binary format.
+///
+/// Values are read from an [`AsyncDecoder`], which enforces the same limits
+/// as the [`Decoder`] does.
+pub trait AsyncDecode: Decode + Send + Sized {
+    /// Try to read and decode a value using the given [`AsyncDecoder`].
+    ///
+    /// Types with a fixed encoded size are decoded using their [`Decode`]
+    /// implementation by [`AsyncDecoder::decode()`], this is only called for
+    /// types without one.
+    fn decode_async_with<'a, R: AsyncReader + 'a>(
+        decoder: &'a mut AsyncDecoder<R>,
+    ) -> DecodeFuture<'a, Self>;
+
+    /// Try to read and decode a value from the given `tokio` reader.
+    #[cfg(feature = "tokio")]
+    fn decode_tokio<'a, R: tokio::io::AsyncRead + Send + Unpin + 'a>(
+        reader: &'a mut R,
+    ) -> DecodeFuture<'a, Self> {
+        Box::pin(async move { AsyncDecoder::new(TokioReader(reader)).decode().await })
+    }
+
+    /// Try to read and decode a value from the given `futures-io` reader.
+    #[cfg(feature = "futures-io")]
+    fn decode_futures<'a, R: futures_io::AsyncRead + Send + Unpin + 'a>(
+        reader: &'a mut R,
+    ) -> DecodeFuture<'a, Self> {
+        Box::pin(async move { AsyncDecoder::new(FuturesReader(reader)).decode().await })
+    }
+}
+
+/// Implement `AsyncDecode` for types with a fixed encoded size, which are
+/// always decoded by reading all of their bytes first.
+macro_rules! impl_async_decode_fixed {
+    ($($type:ty),*) => {
+        $(
+            impl AsyncDecode for $type {
+                fn decode_async_with<'a, R: AsyncReader + 'a>(
+                    decoder: &'a mut AsyncDecoder<R>,
+                ) -> DecodeFuture<'a, Self> {
+                    Box::pin(decoder.decode())
+                }
+            }
+        )*
+    };
+}
+
+impl_async_decode_fixed!(
+    u8,
+    bool,
+    u16,
+    u32,
+    u64,
+    u128,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    usize,
+    f32,
+    f64,
+    char,
+    (),
+    NonZeroU8,
+    NonZeroU16,
+    NonZeroU32,
+    NonZeroU64,
+    NonZeroU128,
+    NonZeroUsize,
+    NonZeroI8,
+    NonZeroI16,
+    NonZeroI32,
+    NonZeroI64,
+    NonZeroI128,
+    Duration,
+    SystemTime,
+    StrictFloat<f32>,
+    StrictFloat<f64>
+);
+
+impl<T: AsyncDecode, const N: usize> AsyncDecode for [T; N] {
+    fn decode_async_with<'a, R: AsyncReader + 'a>(
+        decoder: &'a mut AsyncDecoder<R>,
+    ) -> DecodeFuture<'a, Self> {
+        Box::pin(async move {
+            let elements = decoder.decode_elements::<T>(N).await?;
+
+            elements.try_into().map_err(|elements: Vec<T>| {
+                array_length_mismatch(elements.len(), decoder.position())
+            })
+        })
+    }
+}
+
+impl<T: AsyncDecode> AsyncDecode for Vec<T> {
+    fn decode_async_with<'a, R: AsyncReader + 'a>(
+        decoder: &'a mut AsyncDecoder<R>,
+    ) -> DecodeFuture<'a, Self> {
+        Box::pin(async move {
+            let offset = decoder.position();
+            let length = decoder.decode_length().await?;
+            decoder.claim_allocation::<T>(length, offset)?;
+
+            decoder.enter()?;
+            let elements = decoder.decode_elements(length).await;
+            decoder.leave();
+
+            elements
+        })
+    }
+}
+
+/// Implement `AsyncDecode` for types which are decoded from another type that
+/// implements `AsyncDecode`.
+macro_rules! impl_async_decode_from {
+    ($(impl$(<$($generic:ident $(: $bound:path)?),+>)? for $type:ty => $from:ty),* $(,)?) => {
+        $(
+            impl$(<$($generic: AsyncDecode $(+ $bound)?),+>)? AsyncDecode for $type {
+                fn decode_async_with<'a, R: AsyncReader + 'a>(
+                    decoder: &'a mut AsyncDecoder<R>,
+                ) -> DecodeFuture<'a, Self> {
+                    Box::pin(async move { Ok(decoder.decode::<$from>().await?.into()) })
+                }
+            }
+        )*
+    };
+}
+
+impl<T: AsyncDecode> AsyncDecode for Box<T> {
+    fn decode_async_with<'a, R: AsyncReader + 'a>(
+        decoder: &'a mut AsyncDecoder<R>,
+    ) -> DecodeFuture<'a, Self> {
+        Box::pin(async move {
+            match T::ENCODED_SIZE {
+                // Like the `Decode` implementation, decode the value straight
+                // into the `Box` so secrets don't end up on the stack.
+                Some(size) => {
+                    decoder
+                        .decode_buffered(size, |decoder| T::decode_boxed(decoder))
+                        .await
+                }
+                None => Ok(Box::new(decoder.decode().await?)),
+            }
+        })
+    }
+}
+
+impl_async_decode_from!(
+    impl<T: Sync> for Arc<T> => T,
+    impl<T> for Box<[T]> => Vec<T>,
+    impl<T: Sync> for Arc<[T]> => Vec<T>,
+    impl<T> for VecDeque<T> => Vec<T>,
+    impl for Box<str> => String,
+    impl for Arc<str> => String,
+);
+
+#[cfg(feature = "bytes")]
+impl_async_decode_from!(impl for bytes::Bytes => Vec<u8>);
+
+#[cfg(feature = "bytes")]
+impl AsyncDecode for bytes::BytesMut {
+    fn decode_async_with<'a, R: AsyncReader + 'a>(
+        decoder: &'a mut AsyncDecoder<R>,
+    ) -> DecodeFuture<'a, Self> {
+        Box::pin(async move {
+            let offset = decoder.position();
+            let length = decoder.decode_length().await?;
+            decoder.claim_allocation::<u8>(length, offset)?;
+
+            let mut bytes = bytes::BytesMut::zeroed(length);
+            decoder.read_exact(&mut bytes).await?;
+
+            Ok(bytes)
+        })
+    }
+}
+
+impl<T: ToOwned + Sync + ?Sized> AsyncDecode for Cow<'_, T>
+where
+    T::Owned: AsyncDecode,
+{
+    fn decode_async_with<'a, R: AsyncReader + 'a>(
+        decoder: &'a mut AsyncDecoder<R>,
+    ) -> DecodeFuture<'a, Self> {
+        Box::pin(async move { Ok(Cow::Owned(decoder.decode().await?)) })
+    }
+}
+
+impl AsyncDecode for String {
+    fn decode_async_with<'a, R: AsyncReader + 'a>(
+        decoder: &'a mut AsyncDecoder<R>,
+    ) -> DecodeFuture<'a, Self> {
+        Box::pin(async move {
+            let offset = decoder.position();
+            let bytes = decoder.decode::<Vec<u8>>().await?;
+
+            String::from_utf8(bytes).map_err(|e| DecodeError::InvalidUtf8 {
+                source: e.utf8_error(),
+                offset,
+            })
+        })
+    }
+}
+
+impl<T: AsyncDecode + Ord> AsyncDecode for BTreeSet<T> {
+    fn decode_async_with<'a, R: AsyncReader + 'a>(
+        decoder: &'a mut AsyncDecoder<R>,
+    ) -> DecodeFuture<'a, Self> {
+        Box::pin(async move {
+            let offset = decoder.position();
+            let elements = decoder.decode::<Vec<T>>().await?;
+            check_sorted(&elements, offset)?;
+
+            Ok(elements.into_iter().collect())
+        })
+    }
+}
+
+impl<T: AsyncDecode + Ord + Hash, S: BuildHasher + Default + Send> AsyncDecode for HashSet<T, S> {
+    fn decode_async_with<'a, R: AsyncReader + 'a>(
+        decoder: &'a mut AsyncDecoder<R>,
+    ) -> DecodeFuture<'a, Self> {
+        Box::pin(async move {
+            let offset = decoder.position();
+            let elements = decoder.decode::<Vec<T>>().await?;
+            check_sorted(&elements, offset)?;
+
+            Ok(elements.into_iter().collect())
+        })
+    }
+}
+
+impl<K: AsyncDecode + Ord, V: AsyncDecode> AsyncDecode for BTreeMap<K, V> {
+    fn decode_async_with<'a, R: AsyncReader + 'a>(
+        decoder: &'a mut AsyncDecoder<R>,
+    ) -> DecodeFuture<'a, Self> {
+        Box::pin(async move {
+            let offset = decoder.position();
+            let entries = decoder.decode::<Vec<(K, V)>>().await?;
+            check_sorted(entries.iter().map(|(key, _)| key), offset)?;
+
+            Ok(entries.into_iter().collect())
+        })
+    }
+}
+
+impl<K, V, S> AsyncDecode for HashMap<K, V, S>
+where
+    K: AsyncDecode + Ord + Hash,
+    V: AsyncDecode,
+    S: BuildHasher + Default + Send,
+{
+    fn decode_async_with<'a, R: AsyncReader + 'a>(
+        decoder: &'a mut AsyncDecoder<R>,
+    ) -> DecodeFuture<'a, Self> {
+        Box::pin(async move {
+            let offset = decoder.position();
+            let entries = decoder.decode::<Vec<(K, V)>>().await?;
+            check_sorted(entries.iter().map(|(key, _)| key), offset)?;
+
+            Ok(entries.into_iter().collect())
+        })
+    }
+}
+
+impl<T: AsyncDecode> AsyncDecode for Option<T> {
+    fn decode_async_with<'a, R: AsyncReader + 'a>(
+        decoder: &'a mut AsyncDecoder<R>,
+    ) -> DecodeFuture<'a, Self> {
+        Box::pin(async move {
+            let offset = decoder.position();
+            let tag = decoder.decode::<u8>().await?;
+
+            match tag {
+                0 => Ok(None),
+                1 => {
+                    decoder.enter()?;
+                    let value = decoder.decode().await;
+                    decoder.leave();
+
+                    Ok(Some(value?))
+                }
+                _ => Err(DecodeError::InvalidOptionTag { tag, offset }),
+            }
+        })
+    }
+}
+
+macro_rules! impl_async_decode_tuple {
+    ($($name:ident)+) => {
+        impl<$($name: AsyncDecode),+> AsyncDecode for ($($name,)+) {
+            fn decode_async_with<'a, R: AsyncReader + 'a>(
+                decoder: &'a mut AsyncDecoder<R>,
+            ) -> DecodeFuture<'a, Self> {
+                Box::pin(async move { Ok(($(decoder.decode::<$name>().await?,)+)) })
+            }
+        }
+    };
+}
+
+impl_async_decode_tuple!(A);
+impl_async_decode_tuple!(A B);
+impl_async_decode_tuple!(A B C);
+impl_async_decode_tuple!(A B C D);
+impl_async_decode_tuple!(A B C D E);
+impl_async_decode_tuple!(A B C D E F);
+impl_async_decode_tuple!(A B C D E F G);
+impl_async_decode_tuple!(A B C D E F G H);
+impl_async_decode_tuple!(A B C D E F G H I);
+impl_async_decode_tuple!(A B C D E F G H I J);
+impl_async_decode_tuple!(A B C D E F G H I J K);
+impl_async_decode_tuple!(A B C D E F G H I J K L);
+
+/// The asynchronous counterpart of [`Encode::encode()`], which writes values
+/// to an [`AsyncWriter`] piece by piece.
+///
+/// Sequences are written one element at a time, a value is never encoded
+/// into a single buffer as a whole.
+#[derive(Debug)]
+pub struct AsyncEncoder<W> {
+    writer: W,
+    position: usize,
+}
+
+impl<W: AsyncWriter> AsyncEncoder<W> {
+    /// Create a new `AsyncEncoder` writing to the given writer.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            position: 0,
+        }
+    }
+
+    /// Try to encode and write a value, returning how many bytes were written.
+    pub async fn encode<T: AsyncEncode + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<usize, EncodeError> {
+        value.encode_async_with(self).await
+    }
+
+    /// The number of bytes this `AsyncEncoder` has written so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Get back the writer this `AsyncEncoder` wraps.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Write the whole buffer, returning its length.
+    pub async fn write_all(&mut self, mut buf: &[u8]) -> Result<usize, EncodeError> {
+        let length = buf.len();
+
+        while !buf.is_empty() {
+            let writer = &mut self.writer;
+
+            match poll_fn(|cx| writer.poll_write(cx, buf)).await {
+                Ok(0) => return Err(std::io::Error::from(ErrorKind::WriteZero).into()),
+                Ok(written) => {
+                    buf = &buf[written..];
+                    self.position += written;
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(length)
+    }
+}
+
+/// A trait for asynchronously encoding values using the `matrix-pickle`
+/// binary format.
+///
+/// Values are written to an [`AsyncEncoder`] piece by piece, so large values
+/// don't need to be encoded into a buffer as a whole before they are written.
+pub trait AsyncEncode: Encode + Sync {
+    /// Try to encode and write the value using the given [`AsyncEncoder`],
+    /// returning how many bytes were written.
+    ///
+    /// The default implementation encodes the value into a buffer using its
+    /// [`Encode`] implementation and writes the buffer out. Types containing
+    /// large sequences should write their parts one at a time instead.
+    fn encode_async_with<'a, W: AsyncWriter + 'a>(
+        &'a self,
+        encoder: &'a mut AsyncEncoder<W>,
+    ) -> EncodeFuture<'a> {
+        Box::pin(async move {
+            // The encoded value may contain secrets, so the buffer is
+            // zeroized once it's dropped.
+            let mut encoded = SecretBuffer::default();
+            self.encode(&mut encoded)?;
+
+            encoder.write_all(&encoded).await
+        })
+    }
+
+    /// Try to encode a sequence of values, without a length prefix.
+    ///
+    /// This is the asynchronous counterpart of `Encode::encode_elements()`.
+    #[doc(hidden)]
+    fn encode_elements_async<'a, W: AsyncWriter + 'a>(
+        elements: &'a [Self],
+        encoder: &'a mut AsyncEncoder<W>,
+    ) -> EncodeFuture<'a>
+    where
+        Self: Sized,
+    {
+        Box::pin(async move {
+            let mut ret = 0;
+
+            for element in elements {
+                ret += encoder.encode(element).await?;
+            }
+
+            Ok(ret)
+        })
+    }
+
+    /// Try to encode and write the value to the given `tokio` writer.
+    #[cfg(feature = "tokio")]
+    fn encode_tokio<'a, W: tokio::io::AsyncWrite + Send + Unpin + ?Sized>(
+        &'a self,
+        writer: &'a mut W,
+    ) -> EncodeFuture<'a> {
+        Box::pin(async move { AsyncEncoder::new(TokioWriter(writer)).encode(self).await })
+    }
+
+    /// Try to encode and write the value to the given `futures-io` writer.
+    #[cfg(feature = "futures-io")]
+    fn encode_futures<'a, W: futures_io::AsyncWrite + Send + Unpin + ?Sized>(
+        &'a self,
+        writer: &'a mut W,
+    ) -> EncodeFuture<'a> {
+        Box::pin(async move { AsyncEncoder::new(FuturesWriter(writer)).encode(self).await })
+    }
+}
+
+/// Encode the elements in chunks using their [`Encode`] implementation,
+/// writing every chunk out before the next one is encoded.
+fn encode_elements_buffered<'a, T: Encode + Sync, W: AsyncWriter + 'a>(
+    elements: &'a [T],
+    encoder: &'a mut AsyncEncoder<W>,
+) -> EncodeFuture<'a> {
+    Box::pin(async move {
+        let chunk_length = (CHUNK_SIZE / size_of::<T>().max(1)).max(1);
+
+        let mut ret = 0;
+        let mut buffer = SecretBuffer::default();
+
+        for chunk in elements.chunks(chunk_length) {
+            buffer.clear();
+            T::encode_elements(chunk, &mut buffer)?;
+
+            ret += encoder.write_all(&buffer).await?;
+        }
+
+        Ok(ret)
+    })
+}
+
+/// Implement `AsyncEncode` for types with a small encoded size, which are
+/// always encoded into a buffer first.
+macro_rules! impl_async_encode_fixed {
+    ($($type:ty),*) => {
+        $(
+            impl AsyncEncode for $type {
+                fn encode_elements_async<'a, W: AsyncWriter + 'a>(
+                    elements: &'a [Self],
+                    encoder: &'a mut AsyncEncoder<W>,
+                ) -> EncodeFuture<'a> {
+                    encode_elements_buffered(elements, encoder)
+                }
+            }
+        )*
+    };
+}
+
+impl_async_encode_fixed!(
+    bool,
+    u16,
+    u32,
+    u64,
+    u128,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    usize,
+    f32,
+    f64,
+    char,
+    (),
+    NonZeroU8,
+    NonZeroU16,
+    NonZeroU32,
+    NonZeroU64,
+    NonZeroU128,
+    NonZeroUsize,
+    NonZeroI8,
+    NonZeroI16,
+    NonZeroI32,
+    NonZeroI64,
+    NonZeroI128,
+    Duration,
+    SystemTime,
+    StrictFloat<f32>,
+    StrictFloat<f64>
+);
+
+impl AsyncEncode for u8 {
+    fn encode_elements_async<'a, W: AsyncWriter + 'a>(
+        elements: &'a [Self],
+        encoder: &'a mut AsyncEncoder<W>,
+    ) -> EncodeFuture<'a> {
+        Box::pin(encoder.write_all(elements))
+    }
+}
+
+/// Implement `AsyncEncode` for pointer types, which are encoded like the
+/// value they point to.
+macro_rules! impl_async_encode_deref {
+    ($($type:ty),*) => {
+        $(
+            impl<T: AsyncEncode + ?Sized> AsyncEncode for $type {
+                fn encode_async_with<'a, W: AsyncWriter + 'a>(
+                    &'a self,
+                    encoder: &'a mut AsyncEncoder<W>,
+                ) -> EncodeFuture<'a> {
+                    (**self).encode_async_with(encoder)
+                }
+            }
+        )*
+    };
+}
+
+impl_async_encode_deref!(&T, &mut T, Box<T>);
+
+impl<T: AsyncEncode + Send + ?Sized> AsyncEncode for Arc<T> {
+    fn encode_async_with<'a, W: AsyncWriter + 'a>(
+        &'a self,
+        encoder: &'a mut AsyncEncoder<W>,
+    ) -> EncodeFuture<'a> {
+        (**self).encode_async_with(encoder)
+    }
+}
+
+impl<T: AsyncEncode + ToOwned + ?Sized> AsyncEncode for Cow<'_, T>
+where
+    T::Owned: Sync,
+{
+    fn encode_async_with<'a, W: AsyncWriter + 'a>(
+        &'a self,
+        encoder: &'a mut AsyncEncoder<W>,
+    ) -> EncodeFuture<'a> {
+        (**self).encode_async_with(encoder)
+    }
+}
+
+/// Encode a length prefixed sequence made up of one or more slices.
+async fn encode_slices_async<T: AsyncEncode, W: AsyncWriter>(
+    slices: &[&[T]],
+    encoder: &mut AsyncEncoder<W>,
+) -> Result<usize, EncodeError> {
+    let length = slices.iter().map(|slice| slice.len()).sum();
+
+    if length > MAX_ARRAY_LENGTH {
+        Err(EncodeError::ArrayTooBig(length))
+    } else {
+        let mut ret = encoder.encode(&length).await?;
+
+        for slice in slices {
+            ret += T::encode_elements_async(slice, encoder).await?;
+        }
+
+        Ok(ret)
+    }
+}
+
+/// Encode a length prefixed sequence of `length` elements.
+async fn encode_iter_async<'a, T: AsyncEncode + 'a, W: AsyncWriter>(
+    length: usize,
+    elements: impl Iterator<Item = &'a T>,
+    encoder: &mut AsyncEncoder<W>,
+) -> Result<usize, EncodeError> {
+    if length > MAX_ARRAY_LENGTH {
+        Err(EncodeError::ArrayTooBig(length))
+    } else {
+        let mut ret = encoder.encode(&length).await?;
+
+        for element in elements {
+            ret += encoder.encode(element).await?;
+        }
+
+        Ok(ret)
+    }
+}
+
+/// Encode a length prefixed sequence of `length` key/value pairs.
+async fn encode_entries_async<'a, K: AsyncEncode + 'a, V: AsyncEncode + 'a, W: AsyncWriter>(
+    length: usize,
+    entries: impl Iterator<Item = (&'a K, &'a V)>,
+    encoder: &mut AsyncEncoder<W>,
+) -> Result<usize, EncodeError> {
+    if length > MAX_ARRAY_LENGTH {
+        Err(EncodeError::ArrayTooBig(length))
+    } else {
+        let mut ret = encoder.encode(&length).await?;
+
+        for (key, value) in entries {
+            ret += encoder.encode(key).await?;
+            ret += encoder.encode(value).await?;
+        }
+
+        Ok(ret)
+    }
+}
+
+impl<T: AsyncEncode, const N: usize> AsyncEncode for [T; N] {
+    fn encode_async_with<'a, W: AsyncWriter + 'a>(
+        &'a self,
+        encoder: &'a mut AsyncEncoder<W>,
+    ) -> EncodeFuture<'a> {
+        T::encode_elements_async(self, encoder)
+    }
+}
+
+impl<T: AsyncEncode> AsyncEncode for [T] {
+    fn encode_async_with<'a, W: AsyncWriter + 'a>(
+        &'a self,
+        encoder: &'a mut AsyncEncoder<W>,
+    ) -> EncodeFuture<'a> {
+        Box::pin(async move { encode_slices_async(&[self], encoder).await })
+    }
+}
+
+impl<T: AsyncEncode> AsyncEncode for Vec<T> {
+    fn encode_async_with<'a, W: AsyncWriter + 'a>(
+        &'a self,
+        encoder: &'a mut AsyncEncoder<W>,
+    ) -> EncodeFuture<'a> {
+        self.as_slice().encode_async_with(encoder)
+    }
+}
+
+impl<T: AsyncEncode> AsyncEncode for VecDeque<T> {
+    fn encode_async_with<'a, W: AsyncWriter + 'a>(
+        &'a self,
+        encoder: &'a mut AsyncEncoder<W>,
+    ) -> EncodeFuture<'a> {
+        Box::pin(async move {
+            let (front, back) = self.as_slices();
+            encode_slices_async(&[front, back], encoder).await
+        })
+    }
+}
+
+impl AsyncEncode for str {
+    fn encode_async_with<'a, W: AsyncWriter + 'a>(
+        &'a self,
+        encoder: &'a mut AsyncEncoder<W>,
+    ) -> EncodeFuture<'a> {
+        self.as_bytes().encode_async_with(encoder)
+    }
+}
+
+impl AsyncEncode for String {
+    fn encode_async_with<'a, W: AsyncWriter + 'a>(
+        &'a self,
+        encoder: &'a mut AsyncEncoder<W>,
+    ) -> EncodeFuture<'a> {
+        self.as_bytes().encode_async_with(encoder)
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl AsyncEncode for bytes::Bytes {
+    fn encode_async_with<'a, W: AsyncWriter + 'a>(
+        &'a self,
+        encoder: &'a mut AsyncEncoder<W>,
+    ) -> EncodeFuture<'a> {
+        self[..].encode_async_with(encoder)
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl AsyncEncode for bytes::BytesMut {
+    fn encode_async_with<'a, W: AsyncWriter + 'a>(
+        &'a self,
+        encoder: &'a mut AsyncEncoder<W>,
+    ) -> EncodeFuture<'a> {
+        self[..].encode_async_with(encoder)
+    }
+}
+
+impl<T: AsyncEncode> AsyncEncode for BTreeSet<T> {
+    fn encode_async_with<'a, W: AsyncWriter + 'a>(
+        &'a self,
+        encoder: &'a mut AsyncEncoder<W>,
+    ) -> EncodeFuture<'a> {
+        Box::pin(async move { encode_iter_async(self.len(), self.iter(), encoder).await })
+    }
+}
+
+impl<T: AsyncEncode + Ord, S: Sync> AsyncEncode for HashSet<T, S> {
+    fn encode_async_with<'a, W: AsyncWriter + 'a>(
+        &'a self,
+        encoder: &'a mut AsyncEncoder<W>,
+    ) -> EncodeFuture<'a> {
+        Box::pin(async move {
+            // Sort the elements so the encoding doesn't depend on the hasher state.
+            let mut elements: Vec<_> = self.iter().collect();
+            elements.sort_unstable();
+
+            encode_iter_async(elements.len(), elements.into_iter(), encoder).await
+        })
+    }
+}
+
+impl<K: AsyncEncode, V: AsyncEncode> AsyncEncode for BTreeMap<K, V> {
+    fn encode_async_with<'a, W: AsyncWriter + 'a>(
+        &'a self,
+        encoder: &'a mut AsyncEncoder<W>,
+    ) -> EncodeFuture<'a> {
+        Box::pin(async move { encode_entries_async(self.len(), self.iter(), encoder).await })
+    }
+}
+
+impl<K: AsyncEncode + Ord, V: AsyncEncode, S: Sync> AsyncEncode for HashMap<K, V, S> {
+    fn encode_async_with<'a, W: AsyncWriter + 'a>(
+        &'a self,
+        encoder: &'a mut AsyncEncoder<W>,
+    ) -> EncodeFuture<'a> {
+        Box::pin(async move {
+            // Sort the entries so the encoding doesn't depend on the hasher state.
+            let mut entries: Vec<_> = self.iter().collect();
+            entries.sort_unstable_by_key(|&(key, _)| key);
+
+            encode_entries_async(entries.len(), entries.into_iter(), encoder).await
+        })
+    }
+}
+
+impl<T: AsyncEncode> AsyncEncode for Option<T> {
+    fn encode_async_with<'a, W: AsyncWriter + 'a>(
+        &'a self,
+        encoder: &'a mut AsyncEncoder<W>,
+    ) -> EncodeFuture<'a> {
+        Box::pin(async move {
+            match self {
+                None => encoder.encode(&0u8).await,
+                Some(value) => {
+                    let mut ret = encoder.encode(&1u8).await?;
+                    ret += encoder.encode(value).await?;
+
+                    Ok(ret)
+                }
+            }
+        })
+    }
+}
+
+macro_rules! impl_async_encode_tuple {
+    ($($name:ident)+) => {
+        impl<$($name: AsyncEncode),+> AsyncEncode for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode_async_with<'a, W: AsyncWriter + 'a>(
+                &'a self,
+                encoder: &'a mut AsyncEncoder<W>,
+            ) -> EncodeFuture<'a> {
+                Box::pin(async move {
+                    let ($($name,)+) = self;
+                    let mut ret = 0;
+
+                    $(ret += encoder.encode($name).await?;)+
+
+                    Ok(ret)
+                })
+            }
+        }
+    };
+}
+
+impl_async_encode_tuple!(A);
+impl_async_encode_tuple!(A B);
+impl_async_encode_tuple!(A B C);
+impl_async_encode_tuple!(A B C D);
+impl_async_encode_tuple!(A B C D E);
+impl_async_encode_tuple!(A B C D E F);
+impl_async_encode_tuple!(A B C D E F G);
+impl_async_encode_tuple!(A B C D E F G H);
+impl_async_encode_tuple!(A B C D E F G H I);
+impl_async_encode_tuple!(A B C D E F G H I J);
+impl_async_encode_tuple!(A B C D E F G H I J K);
+impl_async_encode_tuple!(A B C D E F G H I J K L);
@@ -0,0 +1,90 @@
+// Copyright 2022 Damir Jelić
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{Decode, DecodeError, Decoder};
+
+/// A trait for decoding values which borrow from the buffer they are decoded
+/// from.
+///
+/// Unlike [`Decode`], this trait allows values such as `&'de [u8]`,
+/// `&'de [u8; N]` and `&'de str` to point directly into the input buffer,
+/// without copying any of the bytes.
+///
+/// Every type that implements [`Decode`] implements `DecodeBorrowed` as well,
+/// so structs containing a mix of owned and borrowed values can derive it.
+/// This also means that a type can't derive both `Decode` and
+/// `DecodeBorrowed`, the two implementations of `DecodeBorrowed` would
+/// conflict.
+pub trait DecodeBorrowed<'de>: Sized {
+    /// Try to decode a value using the given [`Decoder`], borrowing from the
+    /// buffer it reads from.
+    ///
+    /// The limits of the [`DecodeOptions`] of the `Decoder` are enforced for
+    /// the owned values contained in the decoded value.
+    ///
+    /// [`DecodeOptions`]: crate::DecodeOptions
+    fn decode_borrowed_with(decoder: &mut Decoder<&'de [u8]>) -> Result<Self, DecodeError>;
+
+    /// Try to decode a value from the start of the given buffer, advancing
+    /// the buffer past the decoded bytes.
+    ///
+    /// The offsets of the returned errors are relative to the start of the
+    /// buffer.
+    fn decode_borrowed(input: &mut &'de [u8]) -> Result<Self, DecodeError> {
+        let mut decoder = Decoder::new(*input);
+        let value = decoder.decode_borrowed()?;
+        *input = decoder.into_inner();
+
+        Ok(value)
+    }
+
+    /// Try to decode a value from the given byte slice, borrowing from it.
+    fn decode_from_borrowed_slice(buffer: &'de [u8]) -> Result<Self, DecodeError> {
+        Decoder::new(buffer).decode_borrowed()
+    }
+}
+
+impl<'de, T: Decode> DecodeBorrowed<'de> for T {
+    fn decode_borrowed_with(decoder: &mut Decoder<&'de [u8]>) -> Result<Self, DecodeError> {
+        T::decode_with(decoder)
+    }
+}
+
+impl<'de: 'a, 'a> DecodeBorrowed<'de> for &'a [u8] {
+    fn decode_borrowed_with(decoder: &mut Decoder<&'de [u8]>) -> Result<Self, DecodeError> {
+        let length = decoder.decode_length()?;
+
+        decoder.take(length)
+    }
+}
+
+impl<'de: 'a, 'a, const N: usize> DecodeBorrowed<'de> for &'a [u8; N] {
+    fn decode_borrowed_with(decoder: &mut Decoder<&'de [u8]>) -> Result<Self, DecodeError> {
+        let bytes = decoder.take(N)?;
+
+        // `take()` always returns exactly `N` bytes.
+        Ok(bytes
+            .try_into()
+            .expect("The slice has the length of the array"))
+    }
+}
+
+impl<'de: 'a, 'a> DecodeBorrowed<'de> for &'a str {
+    fn decode_borrowed_with(decoder: &mut Decoder<&'de [u8]>) -> Result<Self, DecodeError> {
+        let offset = decoder.position();
+        let bytes = <&[u8]>::decode_borrowed_with(decoder)?;
+
+        core::str::from_utf8(bytes).map_err(|source| DecodeError::InvalidUtf8 { source, offset })
+    }
+}
@@ -0,0 +1,80 @@
+// Copyright 2022 Damir Jelić
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bytes::{Bytes, BytesMut};
+
+use crate::{
+    io::{Read, Write},
+    Decode, DecodeError, Decoder, Encode, EncodeError,
+};
+
+impl Encode for Bytes {
+    fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+        self[..].encode(writer)
+    }
+}
+
+impl Encode for BytesMut {
+    fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+        self[..].encode(writer)
+    }
+}
+
+/// Read `length` bytes into a new `BytesMut`, claiming them from the
+/// allocation budget of the decoder.
+///
+/// The `offset` is the start of the length prefix of the bytes.
+fn read_bytes(
+    decoder: &mut Decoder<impl Read>,
+    length: usize,
+    offset: usize,
+) -> Result<BytesMut, DecodeError> {
+    decoder.claim_allocation::<u8>(length, offset)?;
+
+    let mut bytes = BytesMut::zeroed(length);
+    decoder.read_exact(&mut bytes)?;
+
+    Ok(bytes)
+}
+
+impl Decode for Bytes {
+    fn decode(reader: &mut impl Read) -> Result<Self, DecodeError> {
+        Decoder::new(reader).decode()
+    }
+
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        let offset = decoder.position();
+        let length = decoder.decode_length()?;
+
+        // When decoding from a `Bytes` buffer, hand out a slice of it instead
+        // of copying the bytes.
+        match decoder.take_shared(length) {
+            Some(bytes) => Ok(bytes),
+            None => Ok(read_bytes(decoder, length, offset)?.freeze()),
+        }
+    }
+}
+
+impl Decode for BytesMut {
+    fn decode(reader: &mut impl Read) -> Result<Self, DecodeError> {
+        Decoder::new(reader).decode()
+    }
+
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        let offset = decoder.position();
+        let length = decoder.decode_length()?;
+
+        read_bytes(decoder, length, offset)
+    }
+}
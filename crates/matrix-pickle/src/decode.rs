@@ -11,96 +11,932 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
-use std::io::{Cursor, Read};
+use alloc::{
+    borrow::{Cow, ToOwned},
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    format,
+    rc::Rc,
+    string::String,
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::{
+    mem::size_of,
+    num::{
+        NonZeroI128, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroU128, NonZeroU16,
+        NonZeroU32, NonZeroU64, NonZeroU8, NonZeroUsize,
+    },
+    time::Duration,
+};
+#[cfg(feature = "std")]
+use std::{
+    collections::{HashMap, HashSet},
+    hash::{BuildHasher, Hash},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use crate::{DecodeError, MAX_ARRAY_LENGTH};
+use crate::{
+    io::{self, Read},
+    DecodeError, DecodeOptions, Decoder, Encode, StrictFloat,
+};
+
+/// Implement `Decode::decode()` by decoding the value using a new `Decoder`
+/// with the default limits.
+macro_rules! decode_using_decoder {
+    () => {
+        fn decode(reader: &mut impl Read) -> Result<Self, DecodeError> {
+            Decoder::new(reader).decode()
+        }
+    };
+}
 
 /// A trait for decoding values that were encoded using the `matrix-pickle` binary format.
 pub trait Decode {
+    /// The number of bytes the encoding of every value of this type takes up,
+    /// if all the values of the type are encoded using the same number of
+    /// bytes.
+    ///
+    /// This is used by [`LazySeq`] to find elements without decoding the
+    /// elements before them.
+    ///
+    /// [`LazySeq`]: crate::LazySeq
+    #[doc(hidden)]
+    const ENCODED_SIZE: Option<usize> = None;
+
     /// Try to read and decode a value from the given reader.
     fn decode(reader: &mut impl Read) -> Result<Self, DecodeError>
     where
         Self: Sized;
 
+    /// Try to read and decode a value using the given [`Decoder`], which
+    /// enforces the limits of its [`DecodeOptions`].
+    ///
+    /// The default implementation forwards to [`Decode::decode()`]. Types
+    /// that allocate memory or contain other values should override this and
+    /// decode their contents using the `Decoder`, otherwise the limits are
+    /// tracked separately for each of the contained values.
+    ///
+    /// The offsets of the returned errors are counted from the start of the
+    /// input of the `Decoder`, while the ones returned by
+    /// [`Decode::decode()`] are relative to the start of the value.
+    ///
+    /// [`DecodeOptions`]: crate::DecodeOptions
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError>
+    where
+        Self: Sized,
+    {
+        let start = decoder.position();
+
+        Self::decode(decoder).map_err(|e| e.shift_offset(start))
+    }
+
+    /// Try to read and decode a value from the given reader, overwriting this
+    /// value.
+    ///
+    /// Unlike [`Decode::decode()`], this reuses the memory the value already
+    /// holds where possible, for example the capacity of a `Vec` or the heap
+    /// allocation of a `Box`. If decoding fails, the value may be left
+    /// partially overwritten.
+    fn decode_into(&mut self, reader: &mut impl Read) -> Result<(), DecodeError>
+    where
+        Self: Sized,
+    {
+        Decoder::new(reader).decode_into(self)
+    }
+
+    /// Try to read and decode a value using the given [`Decoder`], overwriting
+    /// this value.
+    ///
+    /// The default implementation replaces the value with one decoded using
+    /// [`Decode::decode_with()`]. Types that hold memory or contain other
+    /// values should override this to reuse the memory they hold.
+    fn decode_into_with(&mut self, decoder: &mut Decoder<impl Read>) -> Result<(), DecodeError>
+    where
+        Self: Sized,
+    {
+        *self = Self::decode_with(decoder)?;
+
+        Ok(())
+    }
+
     /// Try to read and decode a value from the given byte slice.
     fn decode_from_slice(buffer: &[u8]) -> Result<Self, DecodeError>
     where
         Self: Sized,
     {
-        let mut cursor = Cursor::new(buffer);
-        Self::decode(&mut cursor)
+        Decoder::new(buffer).decode()
+    }
+
+    /// Try to decode a value from the given byte slice, failing if the value
+    /// doesn't consume the whole slice.
+    ///
+    /// Unlike [`Decode::decode_from_slice()`], which ignores any bytes left
+    /// over after the value, this returns a [`DecodeError::TrailingBytes`]
+    /// error if the slice is longer than the encoded value.
+    fn decode_from_slice_exact(buffer: &[u8]) -> Result<Self, DecodeError>
+    where
+        Self: Sized,
+    {
+        let (value, remainder) = Self::decode_prefix(buffer)?;
+
+        if remainder.is_empty() {
+            Ok(value)
+        } else {
+            Err(DecodeError::TrailingBytes {
+                remaining: remainder.len(),
+                offset: buffer.len() - remainder.len(),
+            })
+        }
+    }
+
+    /// Try to decode a value from the start of the given byte slice, returning
+    /// the value and the bytes that come after it.
+    ///
+    /// This can be used to decode multiple concatenated values.
+    fn decode_prefix(buffer: &[u8]) -> Result<(Self, &[u8]), DecodeError>
+    where
+        Self: Sized,
+    {
+        let mut decoder = Decoder::new(buffer);
+        let value = decoder.decode()?;
+
+        Ok((value, decoder.into_inner()))
+    }
+
+    /// Try to decode a value from the start of the given [`Buf`], advancing it
+    /// past the decoded bytes.
+    ///
+    /// Use [`Decode::decode_from_bytes()`] to decode `Bytes` values without
+    /// copying them.
+    ///
+    /// [`Buf`]: bytes::Buf
+    #[cfg(feature = "bytes")]
+    fn decode_from_buf(buf: &mut impl bytes::Buf) -> Result<Self, DecodeError>
+    where
+        Self: Sized,
+    {
+        Self::decode_from_buf_with_options(buf, DecodeOptions::default())
+    }
+
+    /// Try to decode a value from the start of the given [`Buf`], advancing it
+    /// past the decoded bytes and enforcing the given [`DecodeOptions`].
+    ///
+    /// [`Buf`]: bytes::Buf
+    #[cfg(feature = "bytes")]
+    fn decode_from_buf_with_options(
+        buf: &mut impl bytes::Buf,
+        options: DecodeOptions,
+    ) -> Result<Self, DecodeError>
+    where
+        Self: Sized,
+    {
+        Decoder::with_options(bytes::Buf::reader(buf), options).decode()
+    }
+
+    /// Try to decode a value from the start of the given [`Bytes`], advancing
+    /// it past the decoded bytes.
+    ///
+    /// The `Bytes` values contained in the decoded value are slices of the
+    /// input, their contents aren't copied.
+    ///
+    /// [`Bytes`]: bytes::Bytes
+    #[cfg(feature = "bytes")]
+    fn decode_from_bytes(input: &mut bytes::Bytes) -> Result<Self, DecodeError>
+    where
+        Self: Sized,
+    {
+        Self::decode_from_bytes_with_options(input, DecodeOptions::default())
+    }
+
+    /// Try to decode a value from the start of the given [`Bytes`], advancing
+    /// it past the decoded bytes and enforcing the given [`DecodeOptions`].
+    ///
+    /// [`Bytes`]: bytes::Bytes
+    #[cfg(feature = "bytes")]
+    fn decode_from_bytes_with_options(
+        input: &mut bytes::Bytes,
+        options: DecodeOptions,
+    ) -> Result<Self, DecodeError>
+    where
+        Self: Sized,
+    {
+        let mut decoder = Decoder::from_shared(input.clone(), options);
+        let value = decoder.decode()?;
+
+        bytes::Buf::advance(input, decoder.position());
+
+        Ok(value)
+    }
+
+    /// Try to read and decode a value directly into a `Box`.
+    ///
+    /// This is used by the `Decode` implementation of `Box<T>`, types can
+    /// override it if they can be decoded without first placing the value on
+    /// the stack.
+    #[doc(hidden)]
+    fn decode_boxed(decoder: &mut Decoder<impl Read>) -> Result<Box<Self>, DecodeError>
+    where
+        Self: Sized,
+    {
+        Ok(Box::new(Self::decode_with(decoder)?))
+    }
+
+    /// Try to read and decode `length` consecutive values, without a length
+    /// prefix.
+    ///
+    /// This is used by the `Decode` implementations of arrays and `Vec<T>`,
+    /// types can override it if a sequence of values can be read in one go.
+    #[doc(hidden)]
+    fn decode_elements(
+        decoder: &mut Decoder<impl Read>,
+        length: usize,
+    ) -> Result<Vec<Self>, DecodeError>
+    where
+        Self: Sized,
+    {
+        let mut elements = Vec::with_capacity(length);
+
+        for index in 0..length {
+            elements.push(Self::decode_with(decoder).map_err(|e| e.at_index(index))?);
+        }
+
+        Ok(elements)
     }
+
+    /// Try to read and decode `N` consecutive values into an array, without a
+    /// length prefix.
+    ///
+    /// This is used by the `Decode` implementation of arrays, types can
+    /// override it if an array of values can be read in one go.
+    #[doc(hidden)]
+    fn decode_array<const N: usize>(
+        decoder: &mut Decoder<impl Read>,
+    ) -> Result<[Self; N], DecodeError>
+    where
+        Self: Sized,
+    {
+        // Collecting the elements into a `Vec` first ensures that the already
+        // decoded elements are dropped if decoding fails halfway through.
+        let elements = Self::decode_elements(decoder, N)?;
+
+        elements.try_into().map_err(|elements: Vec<Self>| {
+            array_length_mismatch(elements.len(), decoder.position())
+        })
+    }
+
+    /// Try to read and decode values overwriting all the given elements,
+    /// without a length prefix.
+    ///
+    /// This is used by [`Decode::decode_into()`] for arrays and `Vec<T>`,
+    /// types can override it if a sequence of values can be read in one go.
+    #[doc(hidden)]
+    fn decode_slice_into(
+        elements: &mut [Self],
+        decoder: &mut Decoder<impl Read>,
+    ) -> Result<(), DecodeError>
+    where
+        Self: Sized,
+    {
+        for (index, element) in elements.iter_mut().enumerate() {
+            element
+                .decode_into_with(decoder)
+                .map_err(|e| e.at_index(index))?;
+        }
+
+        Ok(())
+    }
+
+    /// Try to read and decode `length` values into the given `Vec`, without a
+    /// length prefix.
+    ///
+    /// The existing elements of the `Vec` are overwritten and its capacity is
+    /// reused.
+    #[doc(hidden)]
+    fn decode_vec_into(
+        elements: &mut Vec<Self>,
+        decoder: &mut Decoder<impl Read>,
+        length: usize,
+    ) -> Result<(), DecodeError>
+    where
+        Self: Sized,
+    {
+        elements.truncate(length);
+        Self::decode_slice_into(elements, decoder)?;
+        elements.reserve_exact(length - elements.len());
+
+        for index in elements.len()..length {
+            elements.push(Self::decode_with(decoder).map_err(|e| e.at_index(index))?);
+        }
+
+        Ok(())
+    }
+}
+
+/// Check if the given bytes are the canonical encoding of a value of type `T`.
+///
+/// The bytes need to decode in canonical mode, see
+/// [`DecodeOptions::with_canonical()`], without any trailing bytes, and
+/// encoding the decoded value again needs to produce the very same bytes. This
+/// catches non-canonical encodings accepted by hand-written `Decode`
+/// implementations as well.
+///
+/// [`DecodeOptions::with_canonical()`]: crate::DecodeOptions::with_canonical
+pub fn is_canonical<T: Decode + Encode>(bytes: &[u8]) -> bool {
+    let options = DecodeOptions::new().with_canonical(true);
+    let mut decoder = Decoder::with_options(bytes, options);
+
+    match decoder.decode::<T>() {
+        Ok(value) if decoder.into_inner().is_empty() => {
+            value.encode_to_vec().is_ok_and(|encoded| encoded == bytes)
+        }
+        _ => false,
+    }
+}
+
+/// Add up the encoded sizes of the fields of a struct or tuple, the sum is
+/// only known if the sizes of all fields are known.
+#[doc(hidden)]
+pub const fn sum_encoded_sizes(sizes: &[Option<usize>]) -> Option<usize> {
+    let mut sum = 0usize;
+    let mut i = 0;
+
+    while i < sizes.len() {
+        sum = match sizes[i] {
+            Some(size) => match sum.checked_add(size) {
+                Some(sum) => sum,
+                None => return None,
+            },
+            None => return None,
+        };
+
+        i += 1;
+    }
+
+    Some(sum)
 }
 
 impl Decode for u8 {
-    fn decode(reader: &mut impl Read) -> Result<Self, DecodeError> {
-        let mut buffer = [0u8; 1];
+    const ENCODED_SIZE: Option<usize> = Some(1);
 
-        reader.read_exact(&mut buffer)?;
+    decode_using_decoder!();
+
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        let mut buffer = [0u8; 1];
+        decoder.read_exact(&mut buffer)?;
 
         Ok(buffer[0])
     }
+
+    fn decode_elements(
+        decoder: &mut Decoder<impl Read>,
+        length: usize,
+    ) -> Result<Vec<Self>, DecodeError> {
+        let mut buffer = vec![0u8; length];
+        decoder.read_exact(&mut buffer)?;
+
+        Ok(buffer)
+    }
+
+    fn decode_array<const N: usize>(
+        decoder: &mut Decoder<impl Read>,
+    ) -> Result<[Self; N], DecodeError> {
+        let mut buffer = [0u8; N];
+        decoder.read_exact(&mut buffer)?;
+
+        Ok(buffer)
+    }
+
+    fn decode_slice_into(
+        elements: &mut [Self],
+        decoder: &mut Decoder<impl Read>,
+    ) -> Result<(), DecodeError> {
+        decoder.read_exact(elements)?;
+
+        Ok(())
+    }
+
+    fn decode_vec_into(
+        elements: &mut Vec<Self>,
+        decoder: &mut Decoder<impl Read>,
+        length: usize,
+    ) -> Result<(), DecodeError> {
+        elements.resize(length, 0);
+        decoder.read_exact(elements)?;
+
+        Ok(())
+    }
 }
 
 impl Decode for bool {
-    fn decode(reader: &mut impl Read) -> Result<Self, DecodeError> {
-        let value = u8::decode(reader)?;
+    const ENCODED_SIZE: Option<usize> = Some(1);
+
+    decode_using_decoder!();
+
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        let offset = decoder.position();
+        let value = u8::decode_with(decoder)?;
 
-        Ok(value != 0)
+        match value {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ if decoder.options().canonical() => {
+                Err(DecodeError::NonCanonicalBool { value, offset })
+            }
+            _ => Ok(true),
+        }
     }
 }
 
-impl Decode for u32 {
-    fn decode(reader: &mut impl Read) -> Result<Self, DecodeError> {
-        let mut buffer = [0u8; 4];
-        reader.read_exact(&mut buffer)?;
+macro_rules! impl_decode_int {
+    ($($type:ty),*) => {
+        $(
+            impl Decode for $type {
+                const ENCODED_SIZE: Option<usize> = Some(size_of::<$type>());
 
-        Ok(u32::from_be_bytes(buffer))
-    }
+                decode_using_decoder!();
+
+                fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+                    let mut buffer = [0u8; size_of::<$type>()];
+                    decoder.read_exact(&mut buffer)?;
+
+                    Ok(<$type>::from_be_bytes(buffer))
+                }
+            }
+        )*
+    };
+}
+
+impl_decode_int!(u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+macro_rules! impl_decode_float {
+    ($($type:ty => $bits:ty),*) => {
+        $(
+            impl Decode for $type {
+                const ENCODED_SIZE: Option<usize> = <$bits>::ENCODED_SIZE;
+
+                decode_using_decoder!();
+
+                fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+                    let offset = decoder.position();
+                    let bits = <$bits>::decode_with(decoder)?;
+                    let value = <$type>::from_bits(bits);
+
+                    // In canonical mode only the NaN a `StrictFloat` encodes is
+                    // accepted.
+                    if decoder.options().canonical()
+                        && value.is_nan()
+                        && bits != StrictFloat::<$type>::CANONICAL_NAN_BITS
+                    {
+                        Err(DecodeError::NonCanonicalNan {
+                            bits: bits.into(),
+                            offset,
+                        })
+                    } else {
+                        Ok(value)
+                    }
+                }
+            }
+        )*
+    };
 }
 
+impl_decode_float!(f32 => u32, f64 => u64);
+
 impl Decode for usize {
-    fn decode(reader: &mut impl Read) -> Result<Self, DecodeError> {
-        let size = u32::decode(reader)?;
+    const ENCODED_SIZE: Option<usize> = u32::ENCODED_SIZE;
+
+    decode_using_decoder!();
 
-        size.try_into()
-            .map_err(|_| DecodeError::OutsideUsizeRange(size as u64))
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        let offset = decoder.position();
+        let size = u32::decode_with(decoder)?;
+
+        size.try_into().map_err(|_| DecodeError::OutsideUsizeRange {
+            value: size.into(),
+            offset,
+        })
     }
 }
 
-impl<const N: usize> Decode for [u8; N] {
-    fn decode(reader: &mut impl Read) -> Result<Self, DecodeError> {
-        let mut buffer = [0u8; N];
-        reader.read_exact(&mut buffer)?;
+/// The error for the impossible case of `Decode::decode_elements()` returning
+/// the wrong number of elements for an array.
+pub(crate) fn array_length_mismatch(length: usize, offset: usize) -> DecodeError {
+    DecodeError::IO {
+        source: io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("decoded {length} array elements instead of the expected amount"),
+        ),
+        offset,
+    }
+}
 
-        Ok(buffer)
+impl<T: Decode, const N: usize> Decode for [T; N] {
+    const ENCODED_SIZE: Option<usize> = match T::ENCODED_SIZE {
+        Some(size) => size.checked_mul(N),
+        None => None,
+    };
+
+    decode_using_decoder!();
+
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        T::decode_array(decoder)
+    }
+
+    fn decode_boxed(decoder: &mut Decoder<impl Read>) -> Result<Box<Self>, DecodeError> {
+        // Converting the boxed slice into a boxed array reuses the heap
+        // allocation, so the array never ends up on the stack.
+        let elements = T::decode_elements(decoder, N)?.into_boxed_slice();
+
+        elements
+            .try_into()
+            .map_err(|elements: Box<[T]>| array_length_mismatch(elements.len(), decoder.position()))
+    }
+
+    fn decode_into_with(&mut self, decoder: &mut Decoder<impl Read>) -> Result<(), DecodeError> {
+        T::decode_slice_into(self, decoder)
     }
 }
 
-impl<const N: usize> Decode for Box<[u8; N]> {
-    fn decode(reader: &mut impl Read) -> Result<Self, DecodeError> {
-        let mut buffer = Box::new([0u8; N]);
-        reader.read_exact(buffer.as_mut_slice())?;
+impl<T: Decode> Decode for Box<T> {
+    const ENCODED_SIZE: Option<usize> = T::ENCODED_SIZE;
 
-        Ok(buffer)
+    decode_using_decoder!();
+
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        T::decode_boxed(decoder)
+    }
+
+    fn decode_into_with(&mut self, decoder: &mut Decoder<impl Read>) -> Result<(), DecodeError> {
+        T::decode_into_with(self, decoder)
+    }
+}
+
+impl<T: Decode> Decode for Rc<T> {
+    const ENCODED_SIZE: Option<usize> = T::ENCODED_SIZE;
+
+    decode_using_decoder!();
+
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        Ok(Rc::new(T::decode_with(decoder)?))
+    }
+}
+
+impl<T: Decode> Decode for Arc<T> {
+    const ENCODED_SIZE: Option<usize> = T::ENCODED_SIZE;
+
+    decode_using_decoder!();
+
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        Ok(Arc::new(T::decode_with(decoder)?))
+    }
+}
+
+impl<T: Decode> Decode for Box<[T]> {
+    decode_using_decoder!();
+
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        Ok(Vec::<T>::decode_with(decoder)?.into_boxed_slice())
+    }
+}
+
+impl<T: Decode> Decode for Rc<[T]> {
+    decode_using_decoder!();
+
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        Ok(Vec::<T>::decode_with(decoder)?.into())
+    }
+}
+
+impl<T: Decode> Decode for Arc<[T]> {
+    decode_using_decoder!();
+
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        Ok(Vec::<T>::decode_with(decoder)?.into())
+    }
+}
+
+impl Decode for Box<str> {
+    decode_using_decoder!();
+
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        Ok(String::decode_with(decoder)?.into_boxed_str())
+    }
+}
+
+impl Decode for Rc<str> {
+    decode_using_decoder!();
+
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        Ok(String::decode_with(decoder)?.into())
+    }
+}
+
+impl Decode for Arc<str> {
+    decode_using_decoder!();
+
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        Ok(String::decode_with(decoder)?.into())
+    }
+}
+
+impl<T: ToOwned + ?Sized> Decode for Cow<'_, T>
+where
+    T::Owned: Decode,
+{
+    decode_using_decoder!();
+
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        Ok(Cow::Owned(T::Owned::decode_with(decoder)?))
     }
 }
 
 impl<T: Decode> Decode for Vec<T> {
-    fn decode(reader: &mut impl Read) -> Result<Self, DecodeError> {
-        let length = usize::decode(reader)?;
+    decode_using_decoder!();
 
-        if length > MAX_ARRAY_LENGTH {
-            Err(DecodeError::ArrayTooBig(length))
-        } else {
-            let mut buffer = Vec::with_capacity(length);
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        let offset = decoder.position();
+        let length = decoder.decode_length()?;
+        decoder.claim_allocation::<T>(length, offset)?;
+
+        decoder.nested(|decoder| T::decode_elements(decoder, length))
+    }
+
+    fn decode_into_with(&mut self, decoder: &mut Decoder<impl Read>) -> Result<(), DecodeError> {
+        let offset = decoder.position();
+        let length = decoder.decode_length()?;
+        decoder.claim_allocation::<T>(length, offset)?;
+
+        decoder.nested(|decoder| T::decode_vec_into(self, decoder, length))
+    }
+}
+
+impl<T: Decode> Decode for VecDeque<T> {
+    decode_using_decoder!();
+
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        Ok(Vec::<T>::decode_with(decoder)?.into())
+    }
+}
+
+/// Check that the given keys are in strictly increasing order.
+///
+/// This ensures that maps and sets have a single valid encoding, with no
+/// duplicate keys and the keys sorted in ascending order.
+pub(crate) fn check_sorted<'a, K: Ord + 'a>(
+    keys: impl IntoIterator<Item = &'a K>,
+    offset: usize,
+) -> Result<(), DecodeError> {
+    let mut previous = None;
+
+    for (index, key) in keys.into_iter().enumerate() {
+        if previous.is_some_and(|previous| previous >= key) {
+            return Err(DecodeError::UnsortedKey { index, offset });
+        }
+
+        previous = Some(key);
+    }
+
+    Ok(())
+}
+
+impl<T: Decode + Ord> Decode for BTreeSet<T> {
+    decode_using_decoder!();
+
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        let offset = decoder.position();
+        let elements = Vec::<T>::decode_with(decoder)?;
+        check_sorted(&elements, offset)?;
+
+        Ok(elements.into_iter().collect())
+    }
+}
 
-            for _ in 0..length {
-                let element = T::decode(reader)?;
-                buffer.push(element);
+#[cfg(feature = "std")]
+impl<T: Decode + Ord + Hash, S: BuildHasher + Default> Decode for HashSet<T, S> {
+    decode_using_decoder!();
+
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        let offset = decoder.position();
+        let elements = Vec::<T>::decode_with(decoder)?;
+        check_sorted(&elements, offset)?;
+
+        Ok(elements.into_iter().collect())
+    }
+}
+
+impl<K: Decode + Ord, V: Decode> Decode for BTreeMap<K, V> {
+    decode_using_decoder!();
+
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        let offset = decoder.position();
+        let entries = Vec::<(K, V)>::decode_with(decoder)?;
+        check_sorted(entries.iter().map(|(key, _)| key), offset)?;
+
+        Ok(entries.into_iter().collect())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: Decode + Ord + Hash, V: Decode, S: BuildHasher + Default> Decode for HashMap<K, V, S> {
+    decode_using_decoder!();
+
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        let offset = decoder.position();
+        let entries = Vec::<(K, V)>::decode_with(decoder)?;
+        check_sorted(entries.iter().map(|(key, _)| key), offset)?;
+
+        Ok(entries.into_iter().collect())
+    }
+}
+
+impl Decode for String {
+    decode_using_decoder!();
+
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        let offset = decoder.position();
+        let bytes = Vec::<u8>::decode_with(decoder)?;
+
+        String::from_utf8(bytes).map_err(|e| DecodeError::InvalidUtf8 {
+            source: e.utf8_error(),
+            offset,
+        })
+    }
+
+    fn decode_into_with(&mut self, decoder: &mut Decoder<impl Read>) -> Result<(), DecodeError> {
+        let offset = decoder.position();
+        let mut bytes = core::mem::take(self).into_bytes();
+        bytes.decode_into_with(decoder)?;
+
+        *self = String::from_utf8(bytes).map_err(|e| DecodeError::InvalidUtf8 {
+            source: e.utf8_error(),
+            offset,
+        })?;
+
+        Ok(())
+    }
+}
+
+impl Decode for char {
+    const ENCODED_SIZE: Option<usize> = u32::ENCODED_SIZE;
+
+    decode_using_decoder!();
+
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        let offset = decoder.position();
+        let value = u32::decode_with(decoder)?;
+
+        char::from_u32(value).ok_or(DecodeError::InvalidChar { value, offset })
+    }
+}
+
+macro_rules! impl_decode_non_zero {
+    ($($type:ty => $int:ty),*) => {
+        $(
+            impl Decode for $type {
+                const ENCODED_SIZE: Option<usize> = <$int>::ENCODED_SIZE;
+
+                decode_using_decoder!();
+
+                fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+                    let offset = decoder.position();
+                    let value = <$int>::decode_with(decoder)?;
+
+                    <$type>::new(value).ok_or(DecodeError::InvalidValue {
+                        message: concat!("A ", stringify!($type), " can't be zero"),
+                        offset,
+                    })
+                }
             }
+        )*
+    };
+}
+
+impl_decode_non_zero!(
+    NonZeroU8 => u8,
+    NonZeroU16 => u16,
+    NonZeroU32 => u32,
+    NonZeroU64 => u64,
+    NonZeroU128 => u128,
+    NonZeroUsize => usize,
+    NonZeroI8 => i8,
+    NonZeroI16 => i16,
+    NonZeroI32 => i32,
+    NonZeroI64 => i64,
+    NonZeroI128 => i128
+);
+
+impl Decode for Duration {
+    const ENCODED_SIZE: Option<usize> = sum_encoded_sizes(&[u64::ENCODED_SIZE, u32::ENCODED_SIZE]);
 
-            Ok(buffer)
+    decode_using_decoder!();
+
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        const NANOS_PER_SEC: u32 = 1_000_000_000;
+
+        let offset = decoder.position();
+        let secs = u64::decode_with(decoder)?;
+        let nanos = u32::decode_with(decoder)?;
+
+        if nanos >= NANOS_PER_SEC {
+            Err(DecodeError::InvalidValue {
+                message: "The nanoseconds of a Duration must be less than a second",
+                offset,
+            })
+        } else {
+            Ok(Duration::new(secs, nanos))
         }
     }
 }
+
+#[cfg(feature = "std")]
+impl Decode for SystemTime {
+    const ENCODED_SIZE: Option<usize> = Duration::ENCODED_SIZE;
+
+    decode_using_decoder!();
+
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        let offset = decoder.position();
+        let duration = Duration::decode_with(decoder)?;
+
+        UNIX_EPOCH
+            .checked_add(duration)
+            .ok_or(DecodeError::InvalidValue {
+                message: "The SystemTime can't be represented on this platform",
+                offset,
+            })
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    decode_using_decoder!();
+
+    fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+        let offset = decoder.position();
+        let tag = u8::decode_with(decoder)?;
+
+        match tag {
+            0 => Ok(None),
+            1 => Ok(Some(decoder.nested(T::decode_with)?)),
+            _ => Err(DecodeError::InvalidOptionTag { tag, offset }),
+        }
+    }
+
+    fn decode_into_with(&mut self, decoder: &mut Decoder<impl Read>) -> Result<(), DecodeError> {
+        let offset = decoder.position();
+        let tag = u8::decode_with(decoder)?;
+
+        match (tag, self) {
+            (0, this) => *this = None,
+            (1, Some(value)) => decoder.nested(|decoder| value.decode_into_with(decoder))?,
+            (1, this) => *this = Some(decoder.nested(T::decode_with)?),
+            _ => return Err(DecodeError::InvalidOptionTag { tag, offset }),
+        }
+
+        Ok(())
+    }
+}
+
+impl Decode for () {
+    const ENCODED_SIZE: Option<usize> = Some(0);
+
+    fn decode(_: &mut impl Read) -> Result<Self, DecodeError> {
+        Ok(())
+    }
+}
+
+macro_rules! impl_decode_tuple {
+    ($($name:ident)+) => {
+        impl<$($name: Decode),+> Decode for ($($name,)+) {
+            const ENCODED_SIZE: Option<usize> = sum_encoded_sizes(&[$($name::ENCODED_SIZE),+]);
+
+            decode_using_decoder!();
+
+            fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+                Ok(($($name::decode_with(decoder)?,)+))
+            }
+
+            #[allow(non_snake_case)]
+            fn decode_into_with(&mut self, decoder: &mut Decoder<impl Read>) -> Result<(), DecodeError> {
+                let ($($name,)+) = self;
+                $($name.decode_into_with(decoder)?;)+
+
+                Ok(())
+            }
+        }
+    };
+}
+
+impl_decode_tuple!(A);
+impl_decode_tuple!(A B);
+impl_decode_tuple!(A B C);
+impl_decode_tuple!(A B C D);
+impl_decode_tuple!(A B C D E);
+impl_decode_tuple!(A B C D E F);
+impl_decode_tuple!(A B C D E F G);
+impl_decode_tuple!(A B C D E F G H);
+impl_decode_tuple!(A B C D E F G H I);
+impl_decode_tuple!(A B C D E F G H I J);
+impl_decode_tuple!(A B C D E F G H I J K);
+impl_decode_tuple!(A B C D E F G H I J K L);
@@ -0,0 +1,400 @@
+// Copyright 2022 Damir Jelić
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::mem::size_of;
+
+use crate::{
+    io::{self, Read},
+    Decode, DecodeBorrowed, DecodeError, MAX_ARRAY_LENGTH,
+};
+
+/// The limits a [`Decoder`] enforces while decoding a value.
+///
+/// The defaults allow arrays with up to `u16::MAX` elements, a total of 256 MiB
+/// of allocations and values nested up to 128 levels deep. Non-canonical
+/// encodings are accepted unless [`DecodeOptions::with_canonical()`] is used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeOptions {
+    max_array_length: usize,
+    max_allocation: usize,
+    max_depth: usize,
+    canonical: bool,
+}
+
+impl DecodeOptions {
+    /// Create a new set of options using the default limits.
+    pub const fn new() -> Self {
+        Self {
+            max_array_length: MAX_ARRAY_LENGTH,
+            max_allocation: 256 * 1024 * 1024,
+            max_depth: 128,
+            canonical: false,
+        }
+    }
+
+    /// Set the maximum number of elements a single array, string, map or set
+    /// may contain.
+    pub const fn with_max_array_length(mut self, max_array_length: usize) -> Self {
+        self.max_array_length = max_array_length;
+        self
+    }
+
+    /// Set the maximum number of bytes that may be allocated for the
+    /// contents of arrays, strings, maps and sets, across the whole value that
+    /// is being decoded.
+    pub const fn with_max_allocation(mut self, max_allocation: usize) -> Self {
+        self.max_allocation = max_allocation;
+        self
+    }
+
+    /// Set how many levels deep values may be nested inside each other.
+    pub const fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Set whether only the canonical encoding of a value should be accepted.
+    ///
+    /// In canonical mode every value has exactly one valid encoding, which is
+    /// the one [`Encode`] produces. For example booleans have to be encoded as
+    /// either 0 or 1, while any nonzero byte decodes to `true` otherwise, and
+    /// floats which are NaN have to use the bit pattern of
+    /// [`StrictFloat::CANONICAL_NAN_BITS`].
+    ///
+    /// [`StrictFloat::CANONICAL_NAN_BITS`]: crate::StrictFloat
+    ///
+    /// [`Encode`]: crate::Encode
+    pub const fn with_canonical(mut self, canonical: bool) -> Self {
+        self.canonical = canonical;
+        self
+    }
+
+    /// The maximum number of elements a single array may contain.
+    pub const fn max_array_length(&self) -> usize {
+        self.max_array_length
+    }
+
+    /// The maximum number of bytes that may be allocated while decoding.
+    pub const fn max_allocation(&self) -> usize {
+        self.max_allocation
+    }
+
+    /// The maximum nesting depth of the decoded value.
+    pub const fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Whether only the canonical encoding of a value is accepted.
+    pub const fn canonical(&self) -> bool {
+        self.canonical
+    }
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A reader which keeps track of the [`DecodeOptions`] limits while decoding
+/// a value.
+///
+/// The limits are tracked across the whole value that is being decoded, for
+/// example the allocation budget is shared by all the nested arrays of a
+/// `Vec<Vec<u8>>`.
+///
+/// ```
+/// use matrix_pickle::{DecodeError, DecodeOptions, Decoder, Encode};
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let encoded = vec![vec![0u8; 1024]; 4].encode_to_vec()?;
+///
+/// let options = DecodeOptions::new().with_max_allocation(2048);
+/// let mut decoder = Decoder::with_options(encoded.as_slice(), options);
+///
+/// let error = decoder.decode::<Vec<Vec<u8>>>().unwrap_err();
+///
+/// assert!(matches!(
+///     error.root(),
+///     DecodeError::AllocationLimit { limit: 2048, .. }
+/// ));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Decoder<R> {
+    reader: R,
+    state: DecodeState,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Create a new `Decoder` using the default [`DecodeOptions`].
+    pub fn new(reader: R) -> Self {
+        Self::with_options(reader, DecodeOptions::default())
+    }
+
+    /// Create a new `Decoder` which enforces the given [`DecodeOptions`].
+    pub fn with_options(reader: R, options: DecodeOptions) -> Self {
+        Self::from_state(reader, DecodeState::new(options))
+    }
+
+    /// Create a `Decoder` which continues where the given state left off.
+    pub(crate) fn from_state(reader: R, state: DecodeState) -> Self {
+        Self { reader, state }
+    }
+
+    /// Try to read and decode a value.
+    ///
+    /// If decoding fails, the offset of the error is counted from the start
+    /// of the input of this `Decoder`.
+    pub fn decode<T: Decode>(&mut self) -> Result<T, DecodeError> {
+        T::decode_with(self)
+    }
+
+    /// Try to read and decode a value, overwriting the given value and reusing
+    /// the memory it holds.
+    ///
+    /// See [`Decode::decode_into()`] for details.
+    pub fn decode_into<T: Decode>(&mut self, value: &mut T) -> Result<(), DecodeError> {
+        value.decode_into_with(self)
+    }
+
+    /// Read exactly enough bytes to fill the given buffer.
+    ///
+    /// Unlike [`Read::read_exact()`], a failure is returned as a
+    /// [`DecodeError`] containing the position at which reading failed.
+    pub fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), DecodeError> {
+        Read::read_exact(self, buf).map_err(|source| DecodeError::IO {
+            source,
+            offset: self.position(),
+        })
+    }
+
+    /// The number of bytes this `Decoder` has read so far.
+    pub fn position(&self) -> usize {
+        self.state.position
+    }
+
+    /// The options this `Decoder` enforces.
+    pub fn options(&self) -> &DecodeOptions {
+        &self.state.options
+    }
+
+    /// Get back the reader this `Decoder` wraps.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Get back the reader this `Decoder` wraps and the state of the limits.
+    #[cfg(any(feature = "tokio", feature = "futures-io", feature = "sans-io"))]
+    pub(crate) fn into_parts(self) -> (R, DecodeState) {
+        (self.reader, self.state)
+    }
+
+    /// Split off the next `length` bytes of the input without copying them,
+    /// if the `Decoder` reads from a shared [`Bytes`] buffer.
+    ///
+    /// [`Bytes`]: bytes::Bytes
+    #[cfg(feature = "bytes")]
+    pub(crate) fn take_shared(&mut self, length: usize) -> Option<bytes::Bytes> {
+        let shared = self
+            .state
+            .shared
+            .as_mut()
+            .filter(|shared| shared.len() >= length)?;
+
+        let bytes = shared.split_to(length);
+        self.state.position += length;
+
+        Some(bytes)
+    }
+
+    /// Get a mutable reference to the reader this `Decoder` wraps.
+    ///
+    /// Bytes read directly from the reader are not counted in the position of
+    /// the `Decoder`.
+    pub(crate) fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Give back the allocation budget claimed so far, used when the decoded
+    /// values are handed out one by one instead of being collected.
+    pub(crate) fn reset_allocation(&mut self) {
+        self.state.allocated = 0;
+    }
+
+    /// Decode the length prefix of an array, checking it against the maximum
+    /// array length.
+    pub fn decode_length(&mut self) -> Result<usize, DecodeError> {
+        let offset = self.position();
+        let length = usize::decode_with(self)?;
+
+        self.state.check_length(length, offset)
+    }
+
+    /// Claim space for `length` values of type `T` from the allocation
+    /// budget.
+    ///
+    /// This should be called before allocating memory whose size depends on
+    /// the decoded input. The `offset` is the position of the value the space
+    /// is claimed for, usually the start of its length prefix, and is used as
+    /// the offset of the error.
+    pub fn claim_allocation<T>(&mut self, length: usize, offset: usize) -> Result<(), DecodeError> {
+        self.state.claim_allocation::<T>(length, offset)
+    }
+
+    /// Run the given closure one nesting level deeper, checking the maximum
+    /// nesting depth.
+    pub fn nested<T>(
+        &mut self,
+        decode: impl FnOnce(&mut Self) -> Result<T, DecodeError>,
+    ) -> Result<T, DecodeError> {
+        self.state.enter()?;
+        let ret = decode(self);
+        self.state.leave();
+
+        ret
+    }
+}
+
+impl<'de> Decoder<&'de [u8]> {
+    /// Try to decode a value which borrows from the buffer this `Decoder`
+    /// reads from.
+    ///
+    /// If decoding fails, the offset of the error is counted from the start
+    /// of the input of this `Decoder`.
+    pub fn decode_borrowed<T: DecodeBorrowed<'de>>(&mut self) -> Result<T, DecodeError> {
+        T::decode_borrowed_with(self)
+    }
+
+    /// Split off the next `length` bytes of the input without copying them.
+    pub(crate) fn take(&mut self, length: usize) -> Result<&'de [u8], DecodeError> {
+        if self.reader.len() < length {
+            Err(DecodeError::IO {
+                source: io::ErrorKind::UnexpectedEof.into(),
+                offset: self.state.position,
+            })
+        } else {
+            let (bytes, rest) = self.reader.split_at(length);
+            self.reader = rest;
+            self.state.position += length;
+
+            Ok(bytes)
+        }
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl Decoder<std::io::Empty> {
+    /// Create a `Decoder` which reads from the given shared buffer, `Bytes`
+    /// values are decoded as slices of it.
+    pub(crate) fn from_shared(input: bytes::Bytes, options: DecodeOptions) -> Self {
+        let mut state = DecodeState::new(options);
+        state.shared = Some(input);
+
+        Self::from_state(std::io::empty(), state)
+    }
+}
+
+/// The position and the state of the limits of a decoder, shared between the
+/// [`Decoder`] and its asynchronous counterpart.
+#[derive(Clone, Debug)]
+pub(crate) struct DecodeState {
+    pub(crate) options: DecodeOptions,
+    pub(crate) position: usize,
+    allocated: usize,
+    depth: usize,
+    /// The input, if it is a shared buffer which is read instead of the reader
+    /// of the decoder.
+    #[cfg(feature = "bytes")]
+    shared: Option<bytes::Bytes>,
+}
+
+impl DecodeState {
+    pub(crate) fn new(options: DecodeOptions) -> Self {
+        Self {
+            options,
+            position: 0,
+            allocated: 0,
+            depth: 0,
+            #[cfg(feature = "bytes")]
+            shared: None,
+        }
+    }
+
+    /// Check a decoded array length against the maximum array length.
+    pub(crate) fn check_length(&self, length: usize, offset: usize) -> Result<usize, DecodeError> {
+        if length > self.options.max_array_length {
+            Err(DecodeError::ArrayTooBig {
+                length,
+                limit: self.options.max_array_length,
+                offset,
+            })
+        } else {
+            Ok(length)
+        }
+    }
+
+    pub(crate) fn claim_allocation<T>(
+        &mut self,
+        length: usize,
+        offset: usize,
+    ) -> Result<(), DecodeError> {
+        let allocated = length
+            .checked_mul(size_of::<T>())
+            .and_then(|size| self.allocated.checked_add(size))
+            .filter(|allocated| *allocated <= self.options.max_allocation)
+            .ok_or(DecodeError::AllocationLimit {
+                limit: self.options.max_allocation,
+                offset,
+            })?;
+
+        self.allocated = allocated;
+
+        Ok(())
+    }
+
+    /// Go one nesting level deeper, checking the maximum nesting depth.
+    pub(crate) fn enter(&mut self) -> Result<(), DecodeError> {
+        if self.depth >= self.options.max_depth {
+            Err(DecodeError::DepthLimit {
+                limit: self.options.max_depth,
+                offset: self.position,
+            })
+        } else {
+            self.depth += 1;
+            Ok(())
+        }
+    }
+
+    pub(crate) fn leave(&mut self) {
+        self.depth -= 1;
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        #[cfg(feature = "bytes")]
+        let read = match &mut self.state.shared {
+            Some(shared) => bytes::Buf::reader(shared).read(buf)?,
+            None => self.reader.read(buf)?,
+        };
+        #[cfg(not(feature = "bytes"))]
+        let read = self.reader.read(buf)?;
+
+        self.state.position += read;
+
+        Ok(read)
+    }
+}
@@ -12,9 +12,29 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::io::{Cursor, Write};
+use alloc::{
+    borrow::{Cow, ToOwned},
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    rc::Rc,
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    num::{
+        NonZeroI128, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroU128, NonZeroU16,
+        NonZeroU32, NonZeroU64, NonZeroU8, NonZeroUsize,
+    },
+    time::Duration,
+};
+#[cfg(feature = "std")]
+use std::{
+    collections::{HashMap, HashSet},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use crate::{EncodeError, MAX_ARRAY_LENGTH};
+use crate::{io::Write, EncodeError, MAX_ARRAY_LENGTH};
 
 /// A trait for encoding values into the `matrix-pickle` binary format.
 pub trait Encode {
@@ -23,18 +43,87 @@ pub trait Encode {
 
     /// Try to encode a value into a new `Vec`.
     fn encode_to_vec(&self) -> Result<Vec<u8>, EncodeError> {
-        let buffer = Vec::new();
-        let mut cursor = Cursor::new(buffer);
+        let mut buffer = Vec::new();
+        self.encode(&mut buffer)?;
 
-        self.encode(&mut cursor)?;
+        Ok(buffer)
+    }
+
+    /// Try to encode a value into the given [`BufMut`], returning how many
+    /// bytes were written.
+    ///
+    /// [`BufMut`]: bytes::BufMut
+    #[cfg(feature = "bytes")]
+    fn encode_to_buf(&self, buf: &mut impl bytes::BufMut) -> Result<usize, EncodeError> {
+        self.encode(&mut bytes::BufMut::writer(buf))
+    }
+
+    /// Try to encode a sequence of values, without a length prefix.
+    ///
+    /// This is used by the `Encode` implementations of arrays, types can
+    /// override it if a sequence of values can be written in one go.
+    #[doc(hidden)]
+    fn encode_elements(elements: &[Self], writer: &mut impl Write) -> Result<usize, EncodeError>
+    where
+        Self: Sized,
+    {
+        let mut ret = 0;
+
+        for element in elements {
+            ret += element.encode(writer)?;
+        }
+
+        Ok(ret)
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+        (**self).encode(writer)
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &mut T {
+    fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+        (**self).encode(writer)
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for Box<T> {
+    fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+        (**self).encode(writer)
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for Rc<T> {
+    fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+        (**self).encode(writer)
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for Arc<T> {
+    fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+        (**self).encode(writer)
+    }
+}
 
-        Ok(cursor.into_inner())
+impl<T: Encode + ToOwned + ?Sized> Encode for Cow<'_, T> {
+    fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+        (**self).encode(writer)
     }
 }
 
 impl Encode for u8 {
     fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
-        Ok(writer.write(&[*self])?)
+        writer.write_all(&[*self])?;
+
+        Ok(1)
+    }
+
+    fn encode_elements(elements: &[Self], writer: &mut impl Write) -> Result<usize, EncodeError> {
+        writer.write_all(elements)?;
+
+        Ok(elements.len())
     }
 }
 
@@ -44,18 +133,36 @@ impl Encode for bool {
     }
 }
 
-impl<const N: usize> Encode for [u8; N] {
+impl<T: Encode, const N: usize> Encode for [T; N] {
     fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
-        writer.write_all(self)?;
+        T::encode_elements(self, writer)
+    }
+}
+
+macro_rules! impl_encode_int {
+    ($($type:ty),*) => {
+        $(
+            impl Encode for $type {
+                fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+                    let bytes = self.to_be_bytes();
+                    bytes.encode(writer)
+                }
+            }
+        )*
+    };
+}
+
+impl_encode_int!(u16, u32, u64, u128, i8, i16, i32, i64, i128);
 
-        Ok(N)
+impl Encode for f32 {
+    fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+        self.to_bits().encode(writer)
     }
 }
 
-impl Encode for u32 {
+impl Encode for f64 {
     fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
-        let bytes = self.to_be_bytes();
-        bytes.encode(writer)
+        self.to_bits().encode(writer)
     }
 }
 
@@ -67,20 +174,230 @@ impl Encode for usize {
     }
 }
 
+/// Encode a length prefixed sequence of `length` elements.
+fn encode_elements<'a, T: Encode + 'a>(
+    length: usize,
+    elements: impl IntoIterator<Item = &'a T>,
+    writer: &mut impl Write,
+) -> Result<usize, EncodeError> {
+    if length > MAX_ARRAY_LENGTH {
+        Err(EncodeError::ArrayTooBig(length))
+    } else {
+        let mut ret = length.encode(writer)?;
+
+        for value in elements {
+            ret += value.encode(writer)?;
+        }
+
+        Ok(ret)
+    }
+}
+
+/// Encode a length prefixed sequence made up of one or more slices.
+///
+/// Unlike [`encode_elements()`], this uses [`Encode::encode_elements()`] for
+/// the slices, so a `[u8]` is written out using a single `write_all()` call.
+fn encode_slices<T: Encode>(
+    slices: &[&[T]],
+    writer: &mut impl Write,
+) -> Result<usize, EncodeError> {
+    let length = slices.iter().map(|slice| slice.len()).sum();
+
+    if length > MAX_ARRAY_LENGTH {
+        Err(EncodeError::ArrayTooBig(length))
+    } else {
+        let mut ret = length.encode(writer)?;
+
+        for slice in slices {
+            ret += T::encode_elements(slice, writer)?;
+        }
+
+        Ok(ret)
+    }
+}
+
+/// Encode a length prefixed sequence of `length` key/value pairs.
+fn encode_entries<'a, K: Encode + 'a, V: Encode + 'a>(
+    length: usize,
+    entries: impl IntoIterator<Item = (&'a K, &'a V)>,
+    writer: &mut impl Write,
+) -> Result<usize, EncodeError> {
+    if length > MAX_ARRAY_LENGTH {
+        Err(EncodeError::ArrayTooBig(length))
+    } else {
+        let mut ret = length.encode(writer)?;
+
+        for (key, value) in entries {
+            ret += key.encode(writer)?;
+            ret += value.encode(writer)?;
+        }
+
+        Ok(ret)
+    }
+}
+
 impl<T: Encode> Encode for [T] {
     fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
-        let length = self.len();
+        encode_slices(&[self], writer)
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+        self.as_slice().encode(writer)
+    }
+}
 
-        if length > MAX_ARRAY_LENGTH {
-            Err(EncodeError::ArrayTooBig(length))
-        } else {
-            let mut ret = length.encode(writer)?;
+impl<T: Encode> Encode for VecDeque<T> {
+    fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+        let (front, back) = self.as_slices();
+        encode_slices(&[front, back], writer)
+    }
+}
 
-            for value in self {
-                ret += value.encode(writer)?;
+impl<T: Encode> Encode for BTreeSet<T> {
+    fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+        encode_elements(self.len(), self, writer)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: Encode + Ord, S> Encode for HashSet<T, S> {
+    fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+        // Sort the elements so the encoding doesn't depend on the hasher state.
+        let mut elements: Vec<_> = self.iter().collect();
+        elements.sort_unstable();
+
+        encode_elements(elements.len(), elements, writer)
+    }
+}
+
+impl<K: Encode, V: Encode> Encode for BTreeMap<K, V> {
+    fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+        encode_entries(self.len(), self, writer)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: Encode + Ord, V: Encode, S> Encode for HashMap<K, V, S> {
+    fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+        // Sort the entries so the encoding doesn't depend on the hasher state.
+        let mut entries: Vec<_> = self.iter().collect();
+        entries.sort_unstable_by_key(|&(key, _)| key);
+
+        encode_entries(entries.len(), entries, writer)
+    }
+}
+
+impl Encode for str {
+    fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+        self.as_bytes().encode(writer)
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+        self.as_str().encode(writer)
+    }
+}
+
+impl Encode for char {
+    fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+        u32::from(*self).encode(writer)
+    }
+}
+
+macro_rules! impl_encode_non_zero {
+    ($($type:ty),*) => {
+        $(
+            impl Encode for $type {
+                fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+                    self.get().encode(writer)
+                }
             }
+        )*
+    };
+}
+
+impl_encode_non_zero!(
+    NonZeroU8,
+    NonZeroU16,
+    NonZeroU32,
+    NonZeroU64,
+    NonZeroU128,
+    NonZeroUsize,
+    NonZeroI8,
+    NonZeroI16,
+    NonZeroI32,
+    NonZeroI64,
+    NonZeroI128
+);
+
+impl Encode for Duration {
+    fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+        let mut ret = self.as_secs().encode(writer)?;
+        ret += self.subsec_nanos().encode(writer)?;
+
+        Ok(ret)
+    }
+}
 
-            Ok(ret)
+#[cfg(feature = "std")]
+impl Encode for SystemTime {
+    fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+        let duration = self
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| EncodeError::BeforeUnixEpoch)?;
+
+        duration.encode(writer)
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+        match self {
+            None => 0u8.encode(writer),
+            Some(value) => {
+                let mut ret = 1u8.encode(writer)?;
+                ret += value.encode(writer)?;
+
+                Ok(ret)
+            }
         }
     }
 }
+
+impl Encode for () {
+    fn encode(&self, _: &mut impl Write) -> Result<usize, EncodeError> {
+        Ok(0)
+    }
+}
+
+macro_rules! impl_encode_tuple {
+    ($($name:ident)+) => {
+        impl<$($name: Encode),+> Encode for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+                let ($($name,)+) = self;
+                let mut ret = 0;
+
+                $(ret += $name.encode(writer)?;)+
+
+                Ok(ret)
+            }
+        }
+    };
+}
+
+impl_encode_tuple!(A);
+impl_encode_tuple!(A B);
+impl_encode_tuple!(A B C);
+impl_encode_tuple!(A B C D);
+impl_encode_tuple!(A B C D E);
+impl_encode_tuple!(A B C D E F);
+impl_encode_tuple!(A B C D E F G);
+impl_encode_tuple!(A B C D E F G H);
+impl_encode_tuple!(A B C D E F G H I);
+impl_encode_tuple!(A B C D E F G H I J);
+impl_encode_tuple!(A B C D E F G H I J K);
+impl_encode_tuple!(A B C D E F G H I J K L);
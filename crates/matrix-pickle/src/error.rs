@@ -12,28 +12,417 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use alloc::{boxed::Box, vec, vec::Vec};
+#[cfg(not(feature = "std"))]
+use core::error::Error as StdError;
+use core::{fmt, ops::Range};
+#[cfg(feature = "std")]
+use std::error::Error as StdError;
+
 use thiserror::Error;
 
+use crate::io;
+
 /// Error type describing failure modes for libolm pickle decoding.
+///
+/// Every error contains a byte offset in the input. Errors about an invalid
+/// value point at the start of that value, while IO errors point at the byte
+/// at which reading failed.
+/// Errors returned by derived `Decode` implementations additionally contain
+/// the path to the value which failed to decode, see [`DecodeError::path()`].
+///
+/// New variants may be added in the future, so matching on this enum requires
+/// a wildcard arm.
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum DecodeError {
     /// There was an error while reading from the source of the libolm, usually
     /// not enough data was provided.
-    #[error(transparent)]
-    IO(#[from] std::io::Error),
+    #[error("Reading the input failed at byte offset {offset}: {source}")]
+    IO {
+        /// The underlying IO error.
+        source: io::Error,
+        /// The byte offset at which the error occurred.
+        offset: usize,
+    },
     /// The encoded usize doesn't fit into the usize of the architecture that is
     /// decoding.
     #[error(
-        "The decoded value {0} does not fit into the usize type of this \
-         architecture"
+        "The length or size {value} at byte offset {offset} does not fit into \
+         the usize type of this architecture"
     )]
-    OutsideUsizeRange(u64),
+    OutsideUsizeRange {
+        /// The value which doesn't fit into an usize.
+        value: u64,
+        /// The byte offset at which the error occurred.
+        offset: usize,
+    },
     /// An array in the pickle has too many elements.
-    #[error("An array has too many elements: {0}")]
-    ArrayTooBig(usize),
-    /// TODO
-    #[error("TODO {0}")]
-    UnknownEnumVariant(u8),
+    #[error(
+        "An array at byte offset {offset} claims to have {length} elements, but at most {limit} \
+         elements are allowed"
+    )]
+    ArrayTooBig {
+        /// The number of elements the array claims to have.
+        length: usize,
+        /// The maximum number of elements an array may have.
+        limit: usize,
+        /// The byte offset at which the error occurred.
+        offset: usize,
+    },
+    /// Decoding the value would allocate more memory than the allocation
+    /// budget of the decoder allows.
+    #[error(
+        "Decoding the array at byte offset {offset} would exceed the allocation budget of \
+         {limit} bytes"
+    )]
+    AllocationLimit {
+        /// The allocation budget of the decoder.
+        limit: usize,
+        /// The byte offset at which the error occurred.
+        offset: usize,
+    },
+    /// The value is nested deeper than the decoder allows.
+    #[error(
+        "The value at byte offset {offset} is nested deeper than the maximum depth of {limit} \
+         levels"
+    )]
+    DepthLimit {
+        /// The maximum nesting depth of the decoder.
+        limit: usize,
+        /// The byte offset at which the error occurred.
+        offset: usize,
+    },
+    /// The input contains more bytes than the decoded value needs.
+    #[error(
+        "The input has {remaining} trailing bytes after the decoded value, starting at byte \
+         offset {offset}"
+    )]
+    TrailingBytes {
+        /// The number of bytes which were left over.
+        remaining: usize,
+        /// The byte offset at which the trailing bytes start.
+        offset: usize,
+    },
+    /// The variant number of an enum doesn't correspond to any of its
+    /// variants.
+    #[error(
+        "The variant number {variant} at byte offset {offset} is not a valid variant of the \
+         enum {type_name}, expected a number in the range {variants:?}"
+    )]
+    UnknownEnumVariant {
+        /// The name of the enum that is being decoded.
+        type_name: &'static str,
+        /// The decoded variant number.
+        variant: u8,
+        /// The range of valid variant numbers.
+        variants: Range<u16>,
+        /// The byte offset at which the error occurred.
+        offset: usize,
+    },
+    /// A string in the pickle does not contain valid UTF-8.
+    #[error("The string at byte offset {offset} is not valid UTF-8: {source}")]
+    InvalidUtf8 {
+        /// The UTF-8 validation error.
+        source: core::str::Utf8Error,
+        /// The byte offset at which the error occurred.
+        offset: usize,
+    },
+    /// The decoded value is not a valid Unicode scalar value and can't be
+    /// converted into a `char`.
+    #[error(
+        "The value {value:#x} at byte offset {offset} is not a Unicode scalar value and can't be \
+         decoded as a char"
+    )]
+    InvalidChar {
+        /// The decoded value.
+        value: u32,
+        /// The byte offset at which the error occurred.
+        offset: usize,
+    },
+    /// The presence tag of an `Option` was neither 0 nor 1.
+    #[error(
+        "The presence tag of an optional value must be 0 or 1, got {tag} at byte offset {offset}"
+    )]
+    InvalidOptionTag {
+        /// The decoded presence tag.
+        tag: u8,
+        /// The byte offset at which the error occurred.
+        offset: usize,
+    },
+    /// The keys of a map or set are either duplicated or not sorted in
+    /// ascending order.
+    #[error(
+        "The key at index {index} of the map or set starting at byte offset {offset} is a \
+         duplicate or not sorted in ascending order"
+    )]
+    UnsortedKey {
+        /// The index of the offending key.
+        index: usize,
+        /// The byte offset at which the error occurred.
+        offset: usize,
+    },
+    /// A floating-point value decoded as a `StrictFloat` or in canonical mode
+    /// is a NaN with a bit pattern other than the canonical one.
+    #[error(
+        "The float at byte offset {offset} is a NaN with the bit pattern {bits:#x} instead of \
+         the canonical NaN"
+    )]
+    NonCanonicalNan {
+        /// The bit pattern of the decoded NaN.
+        bits: u64,
+        /// The byte offset at which the error occurred.
+        offset: usize,
+    },
+    /// A boolean decoded in canonical mode is neither 0 nor 1.
+    #[error("The bool at byte offset {offset} is encoded as {value} instead of 0 or 1")]
+    NonCanonicalBool {
+        /// The byte the boolean was encoded as.
+        value: u8,
+        /// The byte offset at which the error occurred.
+        offset: usize,
+    },
+    /// The decoded value is not valid for the type that is being decoded, for
+    /// example a zero for one of the `NonZero` integer types.
+    #[error("The value at byte offset {offset} is invalid: {message}")]
+    InvalidValue {
+        /// A description of why the value is invalid.
+        message: &'static str,
+        /// The byte offset at which the error occurred.
+        offset: usize,
+    },
+    /// A custom error returned by a hand-written `Decode` implementation, see
+    /// [`DecodeError::custom()`].
+    #[error("Decoding the value at byte offset {offset} failed: {source}")]
+    Custom {
+        /// The underlying error.
+        source: Box<dyn StdError + Send + Sync>,
+        /// The byte offset at which the error occurred.
+        offset: usize,
+    },
+    /// Decoding a field, element or enum variant of a value failed.
+    #[error("Failed to decode {path}: {source}")]
+    Context {
+        /// The path to the value which failed to decode.
+        path: DecodePath,
+        /// The error that occurred while decoding the value.
+        source: Box<DecodeError>,
+    },
+}
+
+impl DecodeError {
+    /// Create a custom error for a hand-written `Decode` implementation.
+    ///
+    /// Offsets of errors returned by [`Decode::decode()`] are relative to the
+    /// start of the value, the error points to the start of the value once
+    /// it's decoded as part of a larger value. Implementations of
+    /// [`Decode::decode_with()`] should use [`DecodeError::Custom`] with the
+    /// position of the [`Decoder`] instead.
+    ///
+    /// [`Decode::decode()`]: crate::Decode::decode
+    /// [`Decode::decode_with()`]: crate::Decode::decode_with
+    /// [`Decoder`]: crate::Decoder
+    pub fn custom(error: impl Into<Box<dyn StdError + Send + Sync>>) -> Self {
+        DecodeError::Custom {
+            source: error.into(),
+            offset: 0,
+        }
+    }
+
+    /// The byte offset in the input at which decoding failed.
+    pub fn offset(&self) -> usize {
+        match self {
+            DecodeError::IO { offset, .. }
+            | DecodeError::OutsideUsizeRange { offset, .. }
+            | DecodeError::ArrayTooBig { offset, .. }
+            | DecodeError::AllocationLimit { offset, .. }
+            | DecodeError::DepthLimit { offset, .. }
+            | DecodeError::TrailingBytes { offset, .. }
+            | DecodeError::UnknownEnumVariant { offset, .. }
+            | DecodeError::InvalidUtf8 { offset, .. }
+            | DecodeError::InvalidChar { offset, .. }
+            | DecodeError::InvalidOptionTag { offset, .. }
+            | DecodeError::UnsortedKey { offset, .. }
+            | DecodeError::NonCanonicalNan { offset, .. }
+            | DecodeError::NonCanonicalBool { offset, .. }
+            | DecodeError::InvalidValue { offset, .. }
+            | DecodeError::Custom { offset, .. } => *offset,
+            DecodeError::Context { source, .. } => source.offset(),
+        }
+    }
+
+    /// The path to the value which failed to decode, if known.
+    ///
+    /// The path is displayed like a Rust place expression, for example
+    /// `Account.one_time_keys[17].public_key`.
+    pub fn path(&self) -> Option<&DecodePath> {
+        match self {
+            DecodeError::Context { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// The error without the path context, describing what actually went
+    /// wrong.
+    pub fn root(&self) -> &DecodeError {
+        match self {
+            DecodeError::Context { source, .. } => source,
+            _ => self,
+        }
+    }
+
+    /// Add the named field of a struct to the front of the path of the error.
+    ///
+    /// Tuple structs use the index of the field as its name. This is used by
+    /// the `Decode` derive, but hand-written `Decode` implementations can use it
+    /// as well.
+    pub fn in_field(self, type_name: &'static str, field: &'static str) -> Self {
+        self.prepend(Some(type_name), PathSegment::Field(field))
+    }
+
+    /// Add the variant of an enum to the front of the path of the error.
+    pub fn in_variant(self, type_name: &'static str, variant: &'static str) -> Self {
+        self.prepend(Some(type_name), PathSegment::Variant(variant))
+    }
+
+    /// Add the index of an array element to the front of the path of the
+    /// error.
+    pub fn at_index(self, index: usize) -> Self {
+        self.prepend(None, PathSegment::Index(index))
+    }
+
+    /// Shift the index at the front of the path of the error, used when the
+    /// elements of a sequence are decoded in chunks.
+    #[cfg(any(feature = "tokio", feature = "futures-io", feature = "sans-io"))]
+    pub(crate) fn shift_index(mut self, shift: usize) -> Self {
+        if let DecodeError::Context { path, .. } = &mut self {
+            if let (None, Some(PathSegment::Index(index))) =
+                (path.type_name, path.segments.first_mut())
+            {
+                *index += shift;
+            }
+        }
+
+        self
+    }
+
+    fn prepend(self, type_name: Option<&'static str>, segment: PathSegment) -> Self {
+        match self {
+            DecodeError::Context { mut path, source } => {
+                // Only the name of the outermost type is part of the path, the
+                // names of the inner types are replaced by the segments.
+                path.type_name = type_name;
+                path.segments.insert(0, segment);
+
+                DecodeError::Context { path, source }
+            }
+            error => DecodeError::Context {
+                path: DecodePath {
+                    type_name,
+                    segments: vec![segment],
+                },
+                source: Box::new(error),
+            },
+        }
+    }
+
+    /// Move the byte offset of the error by the given amount, turning an
+    /// offset relative to the start of a value into one relative to the start
+    /// of the input the value is part of.
+    pub(crate) fn shift_offset(self, shift: usize) -> Self {
+        let offset = self.offset();
+        self.with_offset(offset + shift)
+    }
+
+    /// Replace the byte offset of the error.
+    pub(crate) fn with_offset(mut self, new_offset: usize) -> Self {
+        self.set_offset(new_offset);
+        self
+    }
+
+    fn set_offset(&mut self, new_offset: usize) {
+        match self {
+            DecodeError::IO { offset, .. }
+            | DecodeError::OutsideUsizeRange { offset, .. }
+            | DecodeError::ArrayTooBig { offset, .. }
+            | DecodeError::AllocationLimit { offset, .. }
+            | DecodeError::DepthLimit { offset, .. }
+            | DecodeError::TrailingBytes { offset, .. }
+            | DecodeError::UnknownEnumVariant { offset, .. }
+            | DecodeError::InvalidUtf8 { offset, .. }
+            | DecodeError::InvalidChar { offset, .. }
+            | DecodeError::InvalidOptionTag { offset, .. }
+            | DecodeError::UnsortedKey { offset, .. }
+            | DecodeError::NonCanonicalNan { offset, .. }
+            | DecodeError::NonCanonicalBool { offset, .. }
+            | DecodeError::InvalidValue { offset, .. }
+            | DecodeError::Custom { offset, .. } => *offset = new_offset,
+            DecodeError::Context { source, .. } => source.set_offset(new_offset),
+        }
+    }
+}
+
+/// A single step of the path to a value which failed to decode.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PathSegment {
+    /// A field of a struct.
+    Field(&'static str),
+    /// An element of an array, a `Vec` or another sequence.
+    Index(usize),
+    /// A variant of an enum.
+    Variant(&'static str),
+}
+
+/// The path to a value which failed to decode, made up of the name of the
+/// outermost type followed by field names, indices and enum variants.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DecodePath {
+    type_name: Option<&'static str>,
+    segments: Vec<PathSegment>,
+}
+
+impl DecodePath {
+    /// The name of the outermost type of the path.
+    ///
+    /// This is `None` if the outermost value is not a derived type, for
+    /// example a `Vec`.
+    pub fn type_name(&self) -> Option<&'static str> {
+        self.type_name
+    }
+
+    /// The segments of the path, from the outermost value to the innermost
+    /// one.
+    pub fn segments(&self) -> &[PathSegment] {
+        &self.segments
+    }
+}
+
+impl fmt::Display for DecodePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(type_name) = self.type_name {
+            f.write_str(type_name)?;
+        }
+
+        for segment in &self.segments {
+            match segment {
+                PathSegment::Field(field) => write!(f, ".{field}")?,
+                PathSegment::Index(index) => write!(f, "[{index}]")?,
+                PathSegment::Variant(variant) => write!(f, "::{variant}")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Convert an IO error from a hand-written `Decode` implementation.
+///
+/// Like for [`DecodeError::custom()`], the offset of the error is relative to
+/// the start of the value that is being decoded.
+impl From<io::Error> for DecodeError {
+    fn from(source: io::Error) -> Self {
+        DecodeError::IO { source, offset: 0 }
+    }
 }
 
 /// Error type describing failure modes for libolm pickle decoding.
@@ -41,11 +430,14 @@ pub enum DecodeError {
 pub enum EncodeError {
     /// There was an error while writing to the buffer.
     #[error(transparent)]
-    IO(#[from] std::io::Error),
+    IO(#[from] io::Error),
     /// The usize value that should be encoded doesn't fit into the u32 range of
     /// values.
     #[error("The usize value {0} does not fit into the u32 range of values.")]
     OutsideU32Range(usize),
+    /// A `SystemTime` which lies before the UNIX epoch can't be encoded.
+    #[error("The SystemTime lies before the UNIX epoch")]
+    BeforeUnixEpoch,
     /// An array in the pickle has too many elements.
     #[error("An array has too many elements: {0}")]
     ArrayTooBig(usize),
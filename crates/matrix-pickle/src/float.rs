@@ -0,0 +1,81 @@
+// Copyright 2022 Damir Jelić
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    io::{Read, Write},
+    Decode, DecodeError, Decoder, Encode, EncodeError,
+};
+
+/// A floating-point value which only has a single valid encoding.
+///
+/// Plain `f32` and `f64` values are encoded using their exact bit pattern,
+/// which means that the many different NaN payloads all end up with a
+/// different encoding. A `StrictFloat` encodes every NaN as the canonical
+/// quiet NaN and refuses to decode any other NaN bit pattern, which keeps
+/// pickles byte-for-byte reproducible.
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+pub struct StrictFloat<T>(pub T);
+
+impl<T> From<T> for StrictFloat<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+macro_rules! impl_strict_float {
+    ($type:ty, $bits:ty, $canonical_nan:expr) => {
+        impl StrictFloat<$type> {
+            /// The bit pattern of the only NaN value a `StrictFloat` accepts.
+            pub const CANONICAL_NAN_BITS: $bits = $canonical_nan;
+        }
+
+        impl Encode for StrictFloat<$type> {
+            fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+                let bits = if self.0.is_nan() {
+                    Self::CANONICAL_NAN_BITS
+                } else {
+                    self.0.to_bits()
+                };
+
+                bits.encode(writer)
+            }
+        }
+
+        impl Decode for StrictFloat<$type> {
+            const ENCODED_SIZE: Option<usize> = <$bits>::ENCODED_SIZE;
+
+            fn decode(reader: &mut impl Read) -> Result<Self, DecodeError> {
+                Decoder::new(reader).decode()
+            }
+
+            fn decode_with(decoder: &mut Decoder<impl Read>) -> Result<Self, DecodeError> {
+                let offset = decoder.position();
+                let bits = <$bits>::decode_with(decoder)?;
+                let value = <$type>::from_bits(bits);
+
+                if value.is_nan() && bits != Self::CANONICAL_NAN_BITS {
+                    Err(DecodeError::NonCanonicalNan {
+                        bits: bits.into(),
+                        offset,
+                    })
+                } else {
+                    Ok(Self(value))
+                }
+            }
+        }
+    };
+}
+
+impl_strict_float!(f32, u32, 0x7FC0_0000);
+impl_strict_float!(f64, u64, 0x7FF8_0000_0000_0000);
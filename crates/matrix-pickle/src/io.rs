@@ -0,0 +1,214 @@
+// Copyright 2022 Damir Jelić
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The reader and writer traits used to decode and encode values.
+//!
+//! With the `std` feature enabled these are the traits and the error type of
+//! `std::io`. Without it, minimal replacements are provided which are
+//! implemented for `&[u8]` and `Vec<u8>`, so the crate can be used in `no_std`
+//! environments that have an allocator.
+
+#[cfg(feature = "std")]
+pub use std::io::{Error, ErrorKind, Read, Result, Write};
+
+#[cfg(not(feature = "std"))]
+pub use self::portable::*;
+
+#[cfg(not(feature = "std"))]
+mod portable {
+    use alloc::{string::String, vec::Vec};
+    use core::fmt;
+
+    /// A specialized `Result` type for reading and writing.
+    pub type Result<T> = core::result::Result<T, Error>;
+
+    /// The kinds of errors that can occur while reading or writing.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    #[non_exhaustive]
+    pub enum ErrorKind {
+        /// The reader ran out of bytes before the value was fully read.
+        UnexpectedEof,
+        /// The writer didn't accept any more bytes.
+        WriteZero,
+        /// The operation was interrupted and can be retried.
+        Interrupted,
+        /// The data that was read is invalid.
+        InvalidData,
+        /// Any other error.
+        Other,
+    }
+
+    impl ErrorKind {
+        fn as_str(&self) -> &'static str {
+            match self {
+                ErrorKind::UnexpectedEof => "unexpected end of file",
+                ErrorKind::WriteZero => "write zero",
+                ErrorKind::Interrupted => "operation interrupted",
+                ErrorKind::InvalidData => "invalid data",
+                ErrorKind::Other => "other error",
+            }
+        }
+    }
+
+    impl fmt::Display for ErrorKind {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(self.as_str())
+        }
+    }
+
+    /// The error type for reading and writing, a portable replacement for
+    /// `std::io::Error`.
+    #[derive(Debug)]
+    pub struct Error {
+        kind: ErrorKind,
+        message: Option<String>,
+    }
+
+    impl Error {
+        /// Create a new error of the given kind with a message describing it.
+        pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+            Self {
+                kind,
+                message: Some(message.into()),
+            }
+        }
+
+        /// The kind of this error.
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+
+    impl From<ErrorKind> for Error {
+        fn from(kind: ErrorKind) -> Self {
+            Self {
+                kind,
+                message: None,
+            }
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match &self.message {
+                Some(message) => f.write_str(message),
+                None => self.kind.fmt(f),
+            }
+        }
+    }
+
+    impl core::error::Error for Error {}
+
+    /// A source of bytes, a minimal replacement for `std::io::Read`.
+    pub trait Read {
+        /// Read bytes into the given buffer, returning how many bytes were
+        /// read. Zero bytes signal the end of the input.
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+        /// Read exactly enough bytes to fill the given buffer.
+        fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+            while !buf.is_empty() {
+                match self.read(buf) {
+                    Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                    Ok(read) => buf = &mut buf[read..],
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+
+            Ok(())
+        }
+    }
+
+    /// A sink for bytes, a minimal replacement for `std::io::Write`.
+    pub trait Write {
+        /// Write bytes from the given buffer, returning how many bytes were
+        /// written.
+        fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+        /// Write the whole buffer.
+        fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+            while !buf.is_empty() {
+                match self.write(buf) {
+                    Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                    Ok(written) => buf = &buf[written..],
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+
+            Ok(())
+        }
+    }
+
+    impl Read for &[u8] {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let read = buf.len().min(self.len());
+            let (bytes, rest) = self.split_at(read);
+
+            buf[..read].copy_from_slice(bytes);
+            *self = rest;
+
+            Ok(read)
+        }
+
+        fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+            if self.len() < buf.len() {
+                *self = &self[self.len()..];
+                Err(ErrorKind::UnexpectedEof.into())
+            } else {
+                let (bytes, rest) = self.split_at(buf.len());
+
+                buf.copy_from_slice(bytes);
+                *self = rest;
+
+                Ok(())
+            }
+        }
+    }
+
+    impl<R: Read + ?Sized> Read for &mut R {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            (**self).read(buf)
+        }
+
+        fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+            (**self).read_exact(buf)
+        }
+    }
+
+    impl Write for Vec<u8> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.extend_from_slice(buf);
+
+            Ok(buf.len())
+        }
+
+        fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+            self.extend_from_slice(buf);
+
+            Ok(())
+        }
+    }
+
+    impl<W: Write + ?Sized> Write for &mut W {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            (**self).write(buf)
+        }
+
+        fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+            (**self).write_all(buf)
+        }
+    }
+}
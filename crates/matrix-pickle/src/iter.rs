@@ -0,0 +1,254 @@
+// Copyright 2022 Damir Jelić
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::marker::PhantomData;
+
+use crate::{
+    io::{self, ErrorKind, Read},
+    Decode, DecodeError, DecodeOptions, Decoder,
+};
+
+/// An iterator which decodes values one at a time from a reader, instead of
+/// collecting all of them into a `Vec`.
+///
+/// A `DecodeIter` either decodes the elements of a length prefixed sequence,
+/// see [`DecodeIter::new()`], or a stream of concatenated top-level records
+/// which ends with the input, see [`DecodeIter::records()`].
+///
+/// Each element is decoded using the same limits as a `Vec<T>` would be, but
+/// the allocation budget is given back after every element since the elements
+/// aren't kept around. If an element fails to decode, the error is returned
+/// and the iteration stops.
+///
+/// ```
+/// use matrix_pickle::{DecodeIter, Encode};
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let encoded = vec![String::from("first"), String::from("second")].encode_to_vec()?;
+/// let mut sessions = DecodeIter::<String, _>::new(encoded.as_slice())?;
+///
+/// assert_eq!(sessions.remaining(), Some(2));
+/// assert_eq!(sessions.next().transpose()?.as_deref(), Some("first"));
+/// assert_eq!(sessions.next().transpose()?.as_deref(), Some("second"));
+/// assert!(sessions.next().is_none());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct DecodeIter<T, R> {
+    decoder: Decoder<PeekReader<R>>,
+    remaining: Option<usize>,
+    index: usize,
+    finished: bool,
+    elements: PhantomData<fn() -> T>,
+}
+
+impl<T: Decode, R: Read> DecodeIter<T, R> {
+    /// Read the length prefix of a sequence, the iterator then yields the
+    /// elements of the sequence.
+    ///
+    /// This fails if the sequence has more elements than the maximum array
+    /// length allows.
+    pub fn new(reader: R) -> Result<Self, DecodeError> {
+        Self::with_options(reader, DecodeOptions::default())
+    }
+
+    /// Read the length prefix of a sequence, enforcing the given
+    /// [`DecodeOptions`] for the sequence and its elements.
+    pub fn with_options(reader: R, options: DecodeOptions) -> Result<Self, DecodeError> {
+        let mut decoder = Decoder::with_options(PeekReader::new(reader), options);
+        let length = decoder.decode_length()?;
+
+        Ok(Self::from_decoder(decoder, Some(length)))
+    }
+
+    /// Decode concatenated top-level records until the reader is exhausted.
+    ///
+    /// The iteration ends if the reader has no more bytes left before a
+    /// record, a record which is cut off is an error.
+    pub fn records(reader: R) -> Self {
+        Self::records_with_options(reader, DecodeOptions::default())
+    }
+
+    /// Decode concatenated top-level records until the reader is exhausted,
+    /// enforcing the given [`DecodeOptions`] for every record.
+    pub fn records_with_options(reader: R, options: DecodeOptions) -> Self {
+        Self::from_decoder(
+            Decoder::with_options(PeekReader::new(reader), options),
+            None,
+        )
+    }
+
+    fn from_decoder(decoder: Decoder<PeekReader<R>>, remaining: Option<usize>) -> Self {
+        Self {
+            decoder,
+            remaining,
+            index: 0,
+            finished: false,
+            elements: PhantomData,
+        }
+    }
+
+    /// The number of elements of the sequence which haven't been decoded yet.
+    ///
+    /// This is `None` when decoding records, since their number isn't known
+    /// upfront.
+    pub fn remaining(&self) -> Option<usize> {
+        self.remaining
+    }
+
+    /// The number of bytes that have been read so far.
+    ///
+    /// This is the byte offset of the next element, as long as no element
+    /// failed to decode.
+    pub fn offset(&self) -> usize {
+        self.decoder.position()
+    }
+
+    /// Get back the reader, for example to decode the data following the
+    /// sequence.
+    ///
+    /// When decoding records, the iterator may have read one byte ahead to
+    /// find out if the input has ended, the returned [`PeekReader`] yields
+    /// that byte before the rest of the reader.
+    pub fn into_inner(self) -> PeekReader<R> {
+        self.decoder.into_inner()
+    }
+
+    fn decode_next(&mut self) -> Option<Result<T, DecodeError>> {
+        match &mut self.remaining {
+            Some(0) => return None,
+            Some(remaining) => *remaining -= 1,
+            None => match self.decoder.get_mut().is_at_end() {
+                Ok(true) => return None,
+                Ok(false) => {}
+                Err(e) => return Some(Err(DecodeError::from(e).with_offset(self.offset()))),
+            },
+        }
+
+        self.decoder.reset_allocation();
+
+        let start = self.offset();
+
+        let element = if self.remaining.is_some() {
+            // Elements of a sequence are nested one level deeper, like they
+            // would be in a `Vec<T>`.
+            self.decoder.nested(|decoder| decoder.decode())
+        } else {
+            // A record which doesn't read any bytes would never reach the end
+            // of the input.
+            self.decoder.decode().and_then(|record| {
+                if self.decoder.position() == start {
+                    Err(DecodeError::InvalidValue {
+                        message: "A record must be encoded as at least one byte",
+                        offset: start,
+                    })
+                } else {
+                    Ok(record)
+                }
+            })
+        };
+
+        Some(element.map_err(|e| e.at_index(self.index)))
+    }
+}
+
+impl<T: Decode, R: Read> Iterator for DecodeIter<T, R> {
+    type Item = Result<T, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        let element = self.decode_next();
+        self.index += 1;
+
+        // The position of the next element is unknown if an element fails to
+        // decode, so we stop after the first error.
+        if !matches!(element, Some(Ok(_))) {
+            self.finished = true;
+        }
+
+        element
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.remaining {
+            _ if self.finished => (0, Some(0)),
+            Some(remaining) => (0, Some(remaining)),
+            None => (0, None),
+        }
+    }
+}
+
+/// A reader which can check if the inner reader has any bytes left, without
+/// losing the byte it had to read to find out.
+///
+/// This is returned by [`DecodeIter::into_inner()`], reading from it yields
+/// the byte that was read ahead, if any, followed by the rest of the reader.
+#[derive(Debug)]
+pub struct PeekReader<R> {
+    reader: R,
+    peeked: Option<u8>,
+}
+
+impl<R: Read> PeekReader<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            peeked: None,
+        }
+    }
+
+    /// Split the reader into the byte that was read ahead, if any, and the
+    /// inner reader.
+    pub fn into_parts(self) -> (Option<u8>, R) {
+        (self.peeked, self.reader)
+    }
+
+    fn is_at_end(&mut self) -> io::Result<bool> {
+        if self.peeked.is_none() {
+            let mut byte = [0u8; 1];
+
+            loop {
+                match self.reader.read(&mut byte) {
+                    Ok(0) => return Ok(true),
+                    Ok(_) => break,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+
+            self.peeked = Some(byte[0]);
+        }
+
+        Ok(false)
+    }
+}
+
+impl<R: Read> Read for PeekReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match (self.peeked.take(), buf.first_mut()) {
+            (Some(byte), Some(first)) => {
+                *first = byte;
+                Ok(1)
+            }
+            (peeked, _) => {
+                self.peeked = peeked;
+                self.reader.read(buf)
+            }
+        }
+    }
+}
@@ -0,0 +1,207 @@
+// Copyright 2022 Damir Jelić
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::{fmt, marker::PhantomData};
+
+use crate::{io::ErrorKind, Decode, DecodeError, DecodeOptions, Decoder};
+
+/// A view over an encoded sequence, which only decodes the elements that are
+/// accessed.
+///
+/// The elements need to have an encoding of a fixed size, like integers,
+/// arrays, tuples of those or derived structs made only of those, so that the
+/// position of each element is known without decoding the elements before
+/// it. Using element types with a variable size, like a `Vec` or a `String`,
+/// fails to compile.
+///
+/// ```
+/// use matrix_pickle::{Encode, LazySeq};
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let keys: Vec<[u8; 32]> = (0..100).map(|i| [i; 32]).collect();
+/// let encoded = keys.encode_to_vec()?;
+///
+/// let keys = LazySeq::<[u8; 32]>::new(&encoded)?;
+///
+/// assert_eq!(keys.len(), 100);
+/// assert_eq!(keys.get(17).transpose()?, Some([17; 32]));
+/// assert!(keys.iter().any(|key| matches!(key, Ok(key) if key == [99; 32])));
+/// # Ok(())
+/// # }
+/// ```
+///
+/// ```compile_fail
+/// use matrix_pickle::LazySeq;
+///
+/// let strings = LazySeq::<String>::new(&[0, 0, 0, 0]);
+/// ```
+pub struct LazySeq<'a, T> {
+    elements: &'a [u8],
+    offset: usize,
+    len: usize,
+    options: DecodeOptions,
+    element_type: PhantomData<fn() -> T>,
+}
+
+impl<'a, T: Decode> LazySeq<'a, T> {
+    const ELEMENT_SIZE: usize = match T::ENCODED_SIZE {
+        Some(size) => size,
+        None => panic!("The elements of a LazySeq need to have an encoding of a fixed size"),
+    };
+
+    /// Create a view over the encoded sequence in the given buffer.
+    ///
+    /// This only checks the length prefix of the sequence and that the buffer
+    /// contains all of its elements, the elements themselves are decoded when
+    /// they're accessed. Like [`Decode::decode_from_slice_exact()`], this fails
+    /// if the buffer is longer than the encoded sequence.
+    pub fn new(buffer: &'a [u8]) -> Result<Self, DecodeError> {
+        Self::with_options(buffer, DecodeOptions::default())
+    }
+
+    /// Create a view over the encoded sequence in the given buffer, enforcing
+    /// the given [`DecodeOptions`] for the length prefix and every element
+    /// that is accessed.
+    pub fn with_options(buffer: &'a [u8], options: DecodeOptions) -> Result<Self, DecodeError> {
+        let (sequence, remainder) = Self::from_prefix_with_options(buffer, options)?;
+
+        if remainder.is_empty() {
+            Ok(sequence)
+        } else {
+            Err(DecodeError::TrailingBytes {
+                remaining: remainder.len(),
+                offset: buffer.len() - remainder.len(),
+            })
+        }
+    }
+
+    /// Create a view over the encoded sequence at the start of the given
+    /// buffer, returning the view and the bytes that come after the sequence.
+    pub fn from_prefix(buffer: &'a [u8]) -> Result<(Self, &'a [u8]), DecodeError> {
+        Self::from_prefix_with_options(buffer, DecodeOptions::default())
+    }
+
+    /// Create a view over the encoded sequence at the start of the given
+    /// buffer, enforcing the given [`DecodeOptions`] for the length prefix and
+    /// every element that is accessed.
+    pub fn from_prefix_with_options(
+        buffer: &'a [u8],
+        options: DecodeOptions,
+    ) -> Result<(Self, &'a [u8]), DecodeError> {
+        let mut decoder = Decoder::with_options(buffer, options);
+        let len = decoder.decode_length()?;
+
+        let offset = decoder.position();
+        let rest = decoder.into_inner();
+
+        match len.checked_mul(Self::ELEMENT_SIZE) {
+            Some(size) if size <= rest.len() => {
+                let (elements, remainder) = rest.split_at(size);
+
+                let sequence = Self {
+                    elements,
+                    offset,
+                    len,
+                    options,
+                    element_type: PhantomData,
+                };
+
+                Ok((sequence, remainder))
+            }
+            _ => Err(DecodeError::IO {
+                source: ErrorKind::UnexpectedEof.into(),
+                offset: buffer.len(),
+            }),
+        }
+    }
+
+    /// The number of elements in the sequence.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Is the sequence empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Decode the element at the given index, returns `None` if the index is
+    /// out of bounds.
+    pub fn get(&self, index: usize) -> Option<Result<T, DecodeError>> {
+        let encoded = self.get_encoded(index)?;
+        let offset = self.offset + index * Self::ELEMENT_SIZE;
+
+        let element = Self::decode_element(encoded, self.options)
+            .map_err(|e| e.shift_offset(offset).at_index(index));
+
+        Some(element)
+    }
+
+    /// Decode an element from its encoded bytes, failing if the element
+    /// doesn't consume all of them.
+    fn decode_element(encoded: &[u8], options: DecodeOptions) -> Result<T, DecodeError> {
+        let mut decoder = Decoder::with_options(encoded, options);
+        let element = decoder.decode()?;
+        let remainder = decoder.into_inner();
+
+        if remainder.is_empty() {
+            Ok(element)
+        } else {
+            Err(DecodeError::TrailingBytes {
+                remaining: remainder.len(),
+                offset: encoded.len() - remainder.len(),
+            })
+        }
+    }
+
+    /// Get the encoded bytes of the element at the given index without
+    /// decoding it, returns `None` if the index is out of bounds.
+    pub fn get_encoded(&self, index: usize) -> Option<&'a [u8]> {
+        if index < self.len {
+            let start = index * Self::ELEMENT_SIZE;
+            Some(&self.elements[start..start + Self::ELEMENT_SIZE])
+        } else {
+            None
+        }
+    }
+
+    /// Iterate over the elements of the sequence, decoding each element as
+    /// the iterator advances.
+    pub fn iter(
+        &self,
+    ) -> impl DoubleEndedIterator<Item = Result<T, DecodeError>> + ExactSizeIterator + '_ {
+        (0..self.len).map(move |index| {
+            self.get(index)
+                .expect("The index of the element is always in bounds")
+        })
+    }
+}
+
+impl<T> Clone for LazySeq<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for LazySeq<'_, T> {}
+
+impl<T> fmt::Debug for LazySeq<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LazySeq")
+            .field("offset", &self.offset)
+            .field("len", &self.len)
+            .field("options", &self.options)
+            .finish_non_exhaustive()
+    }
+}
@@ -12,7 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#![doc = include_str!("../README.md")]
+// Most of the examples in the README use the derive macros, so they are only
+// tested if the `derive` feature is enabled.
+#![cfg_attr(any(feature = "derive", not(doctest)), doc = include_str!("../README.md"))]
 #![deny(
     clippy::mem_forget,
     clippy::unwrap_used,
@@ -26,24 +28,60 @@
     unused_qualifications
 )]
 #![cfg_attr(docsrs, feature(doc_auto_cfg))]
+#![cfg_attr(not(feature = "std"), no_std)]
 
+extern crate alloc;
 extern crate self as matrix_pickle;
 
+#[cfg(any(feature = "tokio", feature = "futures-io", feature = "sans-io"))]
+mod async_io;
+mod borrowed;
+#[cfg(feature = "bytes")]
+mod buf;
 mod decode;
+mod decoder;
 mod encode;
 mod error;
+mod float;
+pub mod io;
+mod iter;
+mod lazy;
+#[cfg(feature = "sans-io")]
+mod pull;
+#[cfg(feature = "sans-io")]
+mod push;
 
 const MAX_ARRAY_LENGTH: usize = u16::MAX as usize;
 
 #[cfg(feature = "derive")]
 pub use matrix_pickle_derive::*;
 
+#[cfg(any(feature = "tokio", feature = "futures-io", feature = "sans-io"))]
+pub use async_io::*;
+pub use borrowed::*;
 pub use decode::*;
+pub use decoder::*;
 pub use encode::*;
 pub use error::*;
+pub use float::*;
+pub use iter::*;
+pub use lazy::*;
+#[cfg(feature = "sans-io")]
+pub use pull::*;
+#[cfg(feature = "sans-io")]
+pub use push::*;
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod test {
+    use std::{
+        borrow::Cow,
+        collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+        num::{NonZeroI64, NonZeroU16, NonZeroU32, NonZeroU8, NonZeroUsize},
+        rc::Rc,
+        sync::Arc,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
     use proptest::prelude::*;
 
     use super::*;
@@ -78,21 +116,107 @@ mod test {
     #[test]
     fn encode_cycle() {
         encode_cycle!(10u8 => u8);
+        encode_cycle!(10u16 => u16);
         encode_cycle!(10u32 => u32);
+        encode_cycle!(10u64 => u64);
+        encode_cycle!(10u128 => u128);
+        encode_cycle!(-10i8 => i8);
+        encode_cycle!(-10i16 => i16);
+        encode_cycle!(-10i32 => i32);
+        encode_cycle!(-10i64 => i64);
+        encode_cycle!(-10i128 => i128);
         encode_cycle!(10usize => usize);
         encode_cycle!(true => bool);
         encode_cycle!(false => bool);
         encode_cycle!(vec![1, 2, 3, 4] => Vec<u8>);
+        encode_cycle!(String::from("Hello world") => String);
+        encode_cycle!(String::new() => String);
+        encode_cycle!('a' => char);
+        encode_cycle!('🦀' => char);
+        encode_cycle!(Some(10u32) => Option<u32>);
+        encode_cycle!(None => Option<u32>);
+        encode_cycle!(() => ());
+        encode_cycle!((10u32, [1u8; 32]) => (u32, [u8; 32]));
+        encode_cycle!(
+            (1u8, 2u16, 3u32, 4u64, 5u128, 6i8, 7i16, 8i32, 9i64, 10i128, true, 'c')
+                => (u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, bool, char)
+        );
+        encode_cycle!(Some(Some(vec![1u8, 2])) => Option<Option<Vec<u8>>>);
+        encode_cycle!(VecDeque::from(vec![1u32, 2, 3]) => VecDeque<u32>);
+        encode_cycle!(BTreeSet::from([3u32, 1, 2]) => BTreeSet<u32>);
+        encode_cycle!(HashSet::from([3u32, 1, 2]) => HashSet<u32>);
+        encode_cycle!(BTreeMap::from([(3u32, 'c'), (1, 'a')]) => BTreeMap<u32, char>);
+        encode_cycle!(HashMap::from([(3u32, 'c'), (1, 'a')]) => HashMap<u32, char>);
+        encode_cycle!(Box::new(10u32) => Box<u32>);
+        encode_cycle!(Box::new([1u8; 64]) => Box<[u8; 64]>);
+        encode_cycle!(Rc::new(10u32) => Rc<u32>);
+        encode_cycle!(Arc::new(String::from("Hello")) => Arc<String>);
+        encode_cycle!(vec![1u32, 2].into_boxed_slice() => Box<[u32]>);
+        encode_cycle!(Rc::<[u8]>::from(vec![1u8, 2]) => Rc<[u8]>);
+        encode_cycle!(Arc::<[u8]>::from(vec![1u8, 2]) => Arc<[u8]>);
+        encode_cycle!(Box::<str>::from("Hello") => Box<str>);
+        encode_cycle!(Rc::<str>::from("Hello") => Rc<str>);
+        encode_cycle!(Arc::<str>::from("Hello") => Arc<str>);
+        encode_cycle!(Cow::<[u8]>::Borrowed(&[1u8, 2]) => Cow<[u8]>);
+        encode_cycle!(Cow::<str>::Owned(String::from("Hello")) => Cow<str>);
+        encode_cycle!([1u32, 2, 3, 4] => [u32; 4]);
+        encode_cycle!([[1u8; 32], [2u8; 32], [3u8; 32]] => [[u8; 32]; 3]);
+        encode_cycle!([String::from("a"), String::from("b")] => [String; 2]);
+        encode_cycle!(Box::new([[1u8; 32]; 3]) => Box<[[u8; 32]; 3]>);
+        encode_cycle!([0u8; 0] => [u8; 0]);
+        encode_cycle!(1.5f32 => f32);
+        encode_cycle!(-1.5f64 => f64);
+        encode_cycle!(StrictFloat(1.5f32) => StrictFloat<f32>);
+        encode_cycle!(StrictFloat(f64::INFINITY) => StrictFloat<f64>);
+        encode_cycle!(NonZeroU8::new(u8::MAX).expect("u8::MAX is not zero") => NonZeroU8);
+        encode_cycle!(NonZeroI64::new(i64::MIN).expect("i64::MIN is not zero") => NonZeroI64);
+        encode_cycle!(NonZeroUsize::new(1).expect("One is not zero") => NonZeroUsize);
+        encode_cycle!(Duration::new(10, 999_999_999) => Duration);
+        encode_cycle!(UNIX_EPOCH + Duration::from_millis(1500) => SystemTime);
     }
 
     #[test]
     fn encode_length_check() {
         encode_length_check!(10u8);
+        encode_length_check!(10u16);
         encode_length_check!(10u32);
+        encode_length_check!(10u64);
+        encode_length_check!(10u128);
+        encode_length_check!(-10i8);
+        encode_length_check!(-10i16);
+        encode_length_check!(-10i32);
+        encode_length_check!(-10i64);
+        encode_length_check!(-10i128);
         encode_length_check!(10usize);
         encode_length_check!(true);
         encode_length_check!(false);
         encode_length_check!([1u32, 2u32, 3u32, 4u32]);
+        encode_length_check!([[1u8; 32], [2u8; 32], [3u8; 32]]);
+        encode_length_check!(1.5f32);
+        encode_length_check!(1.5f64);
+        encode_length_check!(StrictFloat(f32::NAN));
+        encode_length_check!(StrictFloat(f64::NAN));
+        encode_length_check!(NonZeroU32::new(1).expect("One is not zero"));
+        encode_length_check!(Duration::from_millis(1500));
+        encode_length_check!(SystemTime::now());
+        encode_length_check!("Hello world");
+        encode_length_check!(String::from("Hello world"));
+        encode_length_check!('🦀');
+        encode_length_check!(Some(10u32));
+        encode_length_check!(None::<u32>);
+        encode_length_check!(());
+        encode_length_check!((10u32, [1u8; 32]));
+        encode_length_check!((1u8, vec![2u8, 3], String::from("four"), Some(5u64)));
+        encode_length_check!(VecDeque::from(vec![1u32, 2, 3]));
+        encode_length_check!(BTreeSet::from([3u32, 1, 2]));
+        encode_length_check!(HashSet::from([3u32, 1, 2]));
+        encode_length_check!(BTreeMap::from([(3u32, 'c'), (1, 'a')]));
+        encode_length_check!(HashMap::from([(3u32, 'c'), (1, 'a')]));
+        encode_length_check!(&10u32);
+        encode_length_check!(&mut 10u32);
+        encode_length_check!(Box::new(10u32));
+        encode_length_check!(Arc::<[u8]>::from(vec![1u8, 2]));
+        encode_length_check!(Cow::<str>::Borrowed("Hello"));
     }
 
     proptest! {
@@ -106,21 +230,559 @@ mod test {
             encode_cycle!(a => u32);
         }
 
+        #[test]
+        fn encode_cycle_u16(a in any::<u16>()) {
+            encode_cycle!(a => u16);
+        }
+
+        #[test]
+        fn encode_cycle_u64(a in any::<u64>()) {
+            encode_cycle!(a => u64);
+        }
+
+        #[test]
+        fn encode_cycle_u128(a in any::<u128>()) {
+            encode_cycle!(a => u128);
+        }
+
+        #[test]
+        fn encode_cycle_i8(a in any::<i8>()) {
+            encode_cycle!(a => i8);
+        }
+
+        #[test]
+        fn encode_cycle_i16(a in any::<i16>()) {
+            encode_cycle!(a => i16);
+        }
+
+        #[test]
+        fn encode_cycle_i32(a in any::<i32>()) {
+            encode_cycle!(a => i32);
+        }
+
+        #[test]
+        fn encode_cycle_i64(a in any::<i64>()) {
+            encode_cycle!(a => i64);
+        }
+
+        #[test]
+        fn encode_cycle_i128(a in any::<i128>()) {
+            encode_cycle!(a => i128);
+        }
+
         #[test]
         fn encode_cycle_usize(a in 0..u32::MAX) {
             let a = a as usize;
             encode_cycle!(a => usize);
         }
 
+        #[test]
+        fn encode_cycle_string(a in ".*") {
+            encode_cycle!(a => String);
+        }
+
+        #[test]
+        fn encode_cycle_char(a in any::<char>()) {
+            encode_cycle!(a => char);
+        }
+
+        #[test]
+        fn encode_cycle_option(a in any::<Option<u64>>()) {
+            encode_cycle!(a => Option<u64>);
+        }
+
+        #[test]
+        fn encode_cycle_tuple(a in any::<(u32, [u8; 32])>()) {
+            encode_cycle!(a => (u32, [u8; 32]));
+        }
+
+        #[test]
+        fn encode_cycle_btree_map(a in any::<BTreeMap<u16, Vec<u8>>>()) {
+            encode_cycle!(a => BTreeMap<u16, Vec<u8>>);
+        }
+
+        #[test]
+        fn encode_cycle_hash_map(a in any::<HashMap<u16, Vec<u8>>>()) {
+            encode_cycle!(a => HashMap<u16, Vec<u8>>);
+        }
+
+        #[test]
+        fn encode_cycle_hash_set(a in any::<HashSet<i32>>()) {
+            encode_cycle!(a => HashSet<i32>);
+        }
+
+        #[test]
+        fn hash_map_encoding_is_canonical(a in any::<HashMap<u16, u8>>()) {
+            let sorted: BTreeMap<_, _> = a.clone().into_iter().collect();
+
+            prop_assert_eq!(
+                a.encode_to_vec().expect("We can always encode into to a Vec"),
+                sorted.encode_to_vec().expect("We can always encode into to a Vec")
+            );
+        }
+
+        #[test]
+        fn encode_cycle_array(a in any::<[i64; 8]>()) {
+            encode_cycle!(a => [i64; 8]);
+        }
+
+        #[test]
+        fn encode_cycle_f32(a in any::<u32>()) {
+            let a = f32::from_bits(a);
+            let encoded = a.encode_to_vec().expect("We can always encode into to a Vec");
+            let decoded = f32::decode_from_slice(&encoded).expect("Decoding a freshly encoded value always works");
+
+            prop_assert_eq!(a.to_bits(), decoded.to_bits());
+        }
+
+        #[test]
+        fn encode_cycle_f64(a in any::<u64>()) {
+            let a = f64::from_bits(a);
+            let encoded = a.encode_to_vec().expect("We can always encode into to a Vec");
+            let decoded = f64::decode_from_slice(&encoded).expect("Decoding a freshly encoded value always works");
+
+            prop_assert_eq!(a.to_bits(), decoded.to_bits());
+        }
+
+        #[test]
+        fn strict_float_is_canonical(a in any::<u64>()) {
+            let a = StrictFloat(f64::from_bits(a));
+            let encoded = a.encode_to_vec().expect("We can always encode into to a Vec");
+            let decoded = StrictFloat::<f64>::decode_from_slice(&encoded)
+                .expect("Decoding a freshly encoded value always works");
+
+            if a.0.is_nan() {
+                prop_assert_eq!(decoded.0.to_bits(), StrictFloat::<f64>::CANONICAL_NAN_BITS);
+            } else {
+                prop_assert_eq!(a, decoded);
+            }
+        }
+
+        #[test]
+        fn encode_cycle_duration(secs in any::<u64>(), nanos in 0..1_000_000_000u32) {
+            encode_cycle!(Duration::new(secs, nanos) => Duration);
+        }
+
+        #[test]
         fn encode_cycle_vec(bytes in prop::collection::vec(any::<u8>(), 0..1000)) {
             encode_cycle!(bytes => Vec<u8>);
         }
+
+        #[test]
+        fn bulk_byte_encoding(bytes in prop::collection::vec(any::<u8>(), 0..1000), split in any::<usize>()) {
+            // Encode the bytes one by one, the way sequences of other types are encoded.
+            let mut expected = bytes.len().encode_to_vec().expect("We can always encode into to a Vec");
+
+            for byte in &bytes {
+                byte.encode(&mut expected).expect("We can always encode into to a Vec");
+            }
+
+            prop_assert_eq!(&bytes.encode_to_vec().expect("We can always encode into to a Vec"), &expected);
+            prop_assert_eq!(&bytes.as_slice().encode_to_vec().expect("We can always encode into to a Vec"), &expected);
+
+            // Rotating the deque makes it wrap around, so it consists of two slices.
+            let mut deque = VecDeque::from(bytes.clone());
+            deque.rotate_left(split % bytes.len().max(1));
+
+            let mut expected_deque = deque.len().encode_to_vec().expect("We can always encode into to a Vec");
+
+            for byte in &deque {
+                byte.encode(&mut expected_deque).expect("We can always encode into to a Vec");
+            }
+
+            prop_assert_eq!(deque.encode_to_vec().expect("We can always encode into to a Vec"), expected_deque);
+        }
+
+        #[test]
+        fn bulk_byte_decoding(bytes in prop::collection::vec(any::<u8>(), 0..1000)) {
+            let encoded = bytes.encode_to_vec().expect("We can always encode into to a Vec");
+
+            // Decode the bytes one by one, the way sequences of other types are decoded.
+            let mut reader = encoded.as_slice();
+            let length = usize::decode(&mut reader).expect("The length was just encoded");
+            let expected: Vec<u8> = (0..length)
+                .map(|_| u8::decode(&mut reader).expect("The bytes were just encoded"))
+                .collect();
+
+            prop_assert_eq!(&Vec::<u8>::decode_from_slice_exact(&encoded).expect("The bytes were just encoded"), &expected);
+            prop_assert_eq!(Box::<[u8]>::decode_from_slice_exact(&encoded).expect("The bytes were just encoded"), expected.into_boxed_slice());
+        }
+    }
+
+    #[test]
+    fn bulk_bytes_use_a_single_call() {
+        #[derive(Default)]
+        struct Counter {
+            calls: usize,
+            bytes: Vec<u8>,
+        }
+
+        impl std::io::Write for Counter {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.calls += 1;
+                self.bytes.write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        impl std::io::Read for Counter {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.calls += 1;
+                let length = buf.len().min(self.bytes.len());
+                buf[..length].copy_from_slice(&self.bytes[..length]);
+                self.bytes.drain(..length);
+
+                Ok(length)
+            }
+        }
+
+        let mut counter = Counter::default();
+        vec![1u8; 1000]
+            .encode(&mut counter)
+            .expect("We can always encode into to a Vec");
+
+        // One call for the length prefix and one for the bytes.
+        assert_eq!(counter.calls, 2);
+
+        counter.calls = 0;
+        let decoded = Vec::<u8>::decode(&mut counter).expect("The bytes were just encoded");
+
+        assert_eq!(decoded, vec![1u8; 1000]);
+        assert_eq!(counter.calls, 2);
+    }
+
+    #[test]
+    fn integers_are_big_endian() {
+        assert_eq!(
+            0x0102u16
+                .encode_to_vec()
+                .expect("We can always encode into to a Vec"),
+            [0x01, 0x02]
+        );
+        assert_eq!(
+            (-2i32)
+                .encode_to_vec()
+                .expect("We can always encode into to a Vec"),
+            [0xFF, 0xFF, 0xFF, 0xFE]
+        );
+        assert_eq!(
+            i64::MIN
+                .encode_to_vec()
+                .expect("We can always encode into to a Vec"),
+            [0x80, 0, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let encoded = [0xFFu8, 0xFE][..]
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+
+        assert!(matches!(
+            String::decode_from_slice(&encoded),
+            Err(DecodeError::InvalidUtf8 { .. })
+        ));
+    }
+
+    #[test]
+    fn invalid_char() {
+        let encoded = 0xD800u32
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+
+        assert!(matches!(
+            char::decode_from_slice(&encoded),
+            Err(DecodeError::InvalidChar { value: 0xD800, .. })
+        ));
+    }
+
+    #[test]
+    fn invalid_option_tag() {
+        assert!(matches!(
+            Option::<u8>::decode_from_slice(&[2, 0]),
+            Err(DecodeError::InvalidOptionTag { tag: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn unsorted_keys() {
+        let duplicates = vec![(1u8, 1u8), (1, 2)]
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+
+        assert!(matches!(
+            BTreeMap::<u8, u8>::decode_from_slice(&duplicates),
+            Err(DecodeError::UnsortedKey { index: 1, .. })
+        ));
+        assert!(matches!(
+            HashMap::<u8, u8>::decode_from_slice(&duplicates),
+            Err(DecodeError::UnsortedKey { index: 1, .. })
+        ));
+
+        let unordered = vec![1u8, 3, 2]
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+
+        assert!(matches!(
+            BTreeSet::<u8>::decode_from_slice(&unordered),
+            Err(DecodeError::UnsortedKey { index: 2, .. })
+        ));
+        assert!(matches!(
+            HashSet::<u8>::decode_from_slice(&unordered),
+            Err(DecodeError::UnsortedKey { index: 2, .. })
+        ));
+        VecDeque::<u8>::decode_from_slice(&unordered).expect("A VecDeque can be in any order");
+    }
+
+    #[test]
+    fn pointers_encode_like_their_value() {
+        let value = vec![1u8, 2, 3];
+        let expected = value
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+
+        let encoded = [
+            <&Vec<u8>>::encode_to_vec(&&value),
+            Box::new(value.clone()).encode_to_vec(),
+            Rc::new(value.clone()).encode_to_vec(),
+            Arc::new(value.clone()).encode_to_vec(),
+            Cow::Borrowed(value.as_slice()).encode_to_vec(),
+            value.clone().into_boxed_slice().encode_to_vec(),
+        ];
+
+        for encoded in encoded {
+            assert_eq!(
+                encoded.expect("We can always encode into to a Vec"),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn arrays_have_no_length_prefix() {
+        assert_eq!(
+            [1u16, 2]
+                .encode_to_vec()
+                .expect("We can always encode into to a Vec"),
+            [0, 1, 0, 2]
+        );
+    }
+
+    #[test]
+    fn nested_error_offsets() {
+        /// A value which gets rejected once it has been read, the error points
+        /// to the start of the value.
+        #[derive(Debug)]
+        struct Rejected;
+
+        impl Decode for Rejected {
+            fn decode(reader: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+                Decoder::new(reader).decode()
+            }
+
+            fn decode_with(decoder: &mut Decoder<impl std::io::Read>) -> Result<Self, DecodeError> {
+                let offset = decoder.position();
+                u32::decode_with(decoder)?;
+
+                Err(DecodeError::InvalidValue {
+                    message: "the value is always rejected",
+                    offset,
+                })
+            }
+        }
+
+        let encoded = [0u8; 9];
+        let error = Decoder::new(encoded.as_slice())
+            .decode::<(u8, Rejected)>()
+            .expect_err("The value is always rejected");
+        assert_eq!(error.offset(), 1);
+
+        let error = Decoder::new(&encoded[..3])
+            .decode::<(u8, Rejected)>()
+            .expect_err("The value is truncated");
+        assert!(matches!(error, DecodeError::IO { offset: 3, .. }));
+
+        /// A value which is decoded using a new `Decoder`, the offsets of its
+        /// errors are relative to the start of the value.
+        #[derive(Debug)]
+        struct Name(#[allow(dead_code)] String);
+
+        impl Decode for Name {
+            fn decode(reader: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+                Ok(Name(String::decode(reader)?))
+            }
+        }
+
+        let mut encoded = (7u32, String::from("ab"))
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        encoded[9] = 0xFF;
+
+        let error = <(u32, Name)>::decode_from_slice(&encoded).expect_err("The name isn't UTF-8");
+        let expected = <(u32, String)>::decode_from_slice(&encoded)
+            .expect_err("The string isn't UTF-8")
+            .offset();
+        assert_eq!(error.offset(), expected);
+        assert_eq!(error.offset(), 4);
+    }
+
+    #[test]
+    fn byte_arrays_are_not_allocated() {
+        let encoded = [7u8; 32];
+        let options = DecodeOptions::new().with_max_allocation(0);
+
+        let decoded = Decoder::with_options(encoded.as_slice(), options)
+            .decode::<[u8; 32]>()
+            .expect("Arrays don't count against the allocation limit");
+        assert_eq!(decoded, encoded);
+
+        let decoded = Decoder::with_options(encoded.as_slice(), options)
+            .decode::<[[u8; 16]; 2]>()
+            .expect("Arrays don't count against the allocation limit");
+        assert_eq!(decoded, [[7u8; 16]; 2]);
+    }
+
+    #[test]
+    fn array_decoding_fails_halfway() {
+        let mut encoded = [String::from("a"), String::from("b")]
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        encoded.pop();
+
+        assert!(matches!(
+            <[String; 2]>::decode_from_slice(&encoded)
+                .expect_err("The second string is truncated")
+                .root(),
+            DecodeError::IO { .. }
+        ));
+
+        let encoded = [[0u8, 0, 0, 1, b'a'], [0, 0, 0, 1, 0xFF]].concat();
+        assert!(matches!(
+            <[String; 2]>::decode_from_slice(&encoded)
+                .expect_err("The second string is invalid UTF-8")
+                .root(),
+            DecodeError::InvalidUtf8 { .. }
+        ));
+    }
+
+    #[test]
+    fn non_canonical_nan() {
+        let nan = 0x7FC0_0001u32;
+        let encoded = nan
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+
+        let decoded = f32::decode_from_slice(&encoded).expect("Plain floats accept any NaN");
+        assert_eq!(decoded.to_bits(), nan);
+
+        // Unless they are decoded in canonical mode.
+        let options = DecodeOptions::new().with_canonical(true);
+        assert!(matches!(
+            Decoder::with_options(encoded.as_slice(), options).decode::<f32>(),
+            Err(DecodeError::NonCanonicalNan {
+                bits: 0x7FC0_0001,
+                offset: 0,
+            })
+        ));
+        let decoded = Decoder::with_options([0x7F, 0xC0, 0x00, 0x00].as_slice(), options)
+            .decode::<f32>()
+            .expect("The canonical NaN is accepted");
+        assert!(decoded.is_nan());
+
+        assert!(matches!(
+            StrictFloat::<f32>::decode_from_slice(&encoded),
+            Err(DecodeError::NonCanonicalNan {
+                bits: 0x7FC0_0001,
+                ..
+            })
+        ));
+        assert_eq!(
+            StrictFloat(f32::from_bits(nan))
+                .encode_to_vec()
+                .expect("We can always encode into to a Vec"),
+            [0x7F, 0xC0, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn invalid_values() {
+        assert!(matches!(
+            NonZeroU32::decode_from_slice(&[0, 0, 0, 0]),
+            Err(DecodeError::InvalidValue { .. })
+        ));
+
+        let encoded = (1u64, 1_000_000_000u32)
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        assert!(matches!(
+            Duration::decode_from_slice(&encoded),
+            Err(DecodeError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            SystemTime::decode_from_slice(&encoded),
+            Err(DecodeError::InvalidValue { .. })
+        ));
+
+        let before_epoch = UNIX_EPOCH - Duration::from_secs(1);
+        assert!(matches!(
+            before_epoch.encode_to_vec(),
+            Err(EncodeError::BeforeUnixEpoch)
+        ));
+    }
+
+    #[test]
+    fn decode_borrowed() {
+        let encoded = ([1u8; 32], vec![2u8, 3], String::from("Hello"), 10u32)
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+
+        let mut input = encoded.as_slice();
+        let array = <&[u8; 32]>::decode_borrowed(&mut input).expect("Can decode a borrowed array");
+        let slice = <&[u8]>::decode_borrowed(&mut input).expect("Can decode a borrowed slice");
+        let string = <&str>::decode_borrowed(&mut input).expect("Can decode a borrowed str");
+        let number = u32::decode_borrowed(&mut input).expect("Can decode an owned value");
+
+        assert_eq!(array, &[1u8; 32]);
+        assert_eq!(slice, [2, 3]);
+        assert_eq!(string, "Hello");
+        assert_eq!(number, 10);
+        assert!(input.is_empty());
+
+        assert!(encoded.as_ptr_range().contains(&array.as_ptr()));
+        assert!(encoded.as_ptr_range().contains(&slice.as_ptr()));
+        assert!(encoded.as_ptr_range().contains(&string.as_ptr()));
+
+        assert!(matches!(
+            <&[u8; 32]>::decode_from_borrowed_slice(&encoded[..31]),
+            Err(DecodeError::IO { .. })
+        ));
+        assert!(matches!(
+            <&[u8]>::decode_from_borrowed_slice(&[0, 0, 0, 3, 1, 2]),
+            Err(DecodeError::IO { .. })
+        ));
+        assert!(matches!(
+            <&str>::decode_from_borrowed_slice(&[0, 0, 0, 1, 0xFF]),
+            Err(DecodeError::InvalidUtf8 { offset: 0, .. })
+        ));
+
+        let mut buffer = Vec::<u8>::new();
+        (MAX_ARRAY_LENGTH + 1)
+            .encode(&mut buffer)
+            .expect("Should encode length");
+        assert!(matches!(
+            <&[u8]>::decode_from_borrowed_slice(&buffer),
+            Err(DecodeError::ArrayTooBig { .. })
+        ));
     }
 
     #[test]
     fn max_array_length() {
         assert!(matches!(
-            [false; MAX_ARRAY_LENGTH + 1].encode_to_vec(),
+            [false; MAX_ARRAY_LENGTH + 1][..].encode_to_vec(),
             Err(EncodeError::ArrayTooBig(_))
         ));
 
@@ -130,43 +792,2108 @@ mod test {
             .expect("Should encode length");
         assert!(matches!(
             Vec::<bool>::decode(&mut &*buffer),
-            Err(DecodeError::ArrayTooBig(_))
+            Err(DecodeError::ArrayTooBig { .. })
+        ));
+        assert!(matches!(
+            String::decode(&mut &*buffer),
+            Err(DecodeError::ArrayTooBig { .. })
         ));
     }
 
     #[test]
-    #[cfg(feature = "derive")]
-    fn derive() {
-        #[derive(Clone, Encode, Decode, PartialEq, Debug)]
-        struct Foo {
-            thing: [u8; 32],
-            #[secret]
-            another: Box<[u8; 64]>,
-        }
+    fn decode_exact() {
+        let mut encoded = 10u32
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
 
-        let foo = Foo {
-            thing: [1u8; 32],
-            another: Box::new([2u8; 64]),
-        };
+        assert_eq!(
+            u32::decode_from_slice_exact(&encoded).expect("The slice contains exactly one u32"),
+            10
+        );
 
-        encode_cycle!(foo.clone() => Foo);
+        encoded.extend([1, 2]);
 
-        #[derive(Clone, Encode, Decode, PartialEq, Debug)]
-        struct Bar([u8; 32]);
+        assert_eq!(
+            u32::decode_from_slice(&encoded).expect("Trailing bytes are ignored"),
+            10
+        );
+        assert!(matches!(
+            u32::decode_from_slice_exact(&encoded),
+            Err(DecodeError::TrailingBytes { remaining: 2, .. })
+        ));
+    }
 
-        let bar = Bar([1u8; 32]);
-        encode_cycle!(bar.clone() => Bar);
+    #[test]
+    fn decode_prefix() {
+        let mut encoded = Vec::new();
+        10u32
+            .encode(&mut encoded)
+            .expect("We can always encode into to a Vec");
+        "Hello"
+            .encode(&mut encoded)
+            .expect("We can always encode into to a Vec");
+        [1u8; 3]
+            .encode(&mut encoded)
+            .expect("We can always encode into to a Vec");
 
-        #[derive(Encode, Decode, PartialEq, Debug)]
-        enum Something {
-            Foo(Foo),
-            Bar(Bar),
-        }
+        let (number, rest) = u32::decode_prefix(&encoded).expect("Can decode the first value");
+        let (string, rest) = String::decode_prefix(rest).expect("Can decode the second value");
+        let (array, rest) = <[u8; 3]>::decode_prefix(rest).expect("Can decode the third value");
 
-        let something = Something::Foo(foo);
-        encode_cycle!(something => Something);
+        assert_eq!(number, 10);
+        assert_eq!(string, "Hello");
+        assert_eq!(array, [1u8; 3]);
+        assert!(rest.is_empty());
 
-        let something = Something::Bar(bar);
-        encode_cycle!(something => Something);
+        assert!(matches!(
+            u32::decode_prefix(&encoded[..2]),
+            Err(DecodeError::IO { .. })
+        ));
+    }
+
+    #[test]
+    fn error_offsets() {
+        assert_eq!(
+            u32::decode_from_slice(&[0, 0, 1])
+                .expect_err("Three bytes are not enough for an u32")
+                .offset(),
+            3
+        );
+
+        let encoded = (10u32, Some(1u8), 2u8, 3u8)
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        assert_eq!(encoded[4], 1, "The presence tag is at offset 4");
+
+        let mut corrupted = encoded.clone();
+        corrupted[4] = 5;
+
+        assert!(matches!(
+            <(u32, Option<u8>)>::decode_from_slice(&corrupted),
+            Err(DecodeError::InvalidOptionTag { tag: 5, offset: 4 })
+        ));
+        assert!(matches!(
+            <(u32, Option<u8>)>::decode_from_slice_exact(&encoded),
+            Err(DecodeError::TrailingBytes {
+                remaining: 2,
+                offset: 6
+            })
+        ));
+        assert!(matches!(
+            <&str>::decode_from_borrowed_slice(&encoded),
+            Err(DecodeError::IO { offset: 4, .. })
+        ));
+
+        let encoded = vec![vec![1u8; 4], vec![2u8; 2]]
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        let options = DecodeOptions::new().with_max_array_length(3);
+
+        assert!(matches!(
+            Decoder::with_options(encoded.as_slice(), options)
+                .decode::<Vec<Vec<u8>>>()
+                .expect_err("The inner arrays are too long")
+                .root(),
+            DecodeError::ArrayTooBig {
+                length: 4,
+                limit: 3,
+                offset: 4
+            }
+        ));
+    }
+
+    #[test]
+    fn error_offsets_of_hand_written_impls() {
+        #[derive(Debug)]
+        struct Magic;
+
+        impl Decode for Magic {
+            fn decode(reader: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+                let mut magic = [0u8; 2];
+                reader.read_exact(&mut magic)?;
+
+                if magic == *b"MP" {
+                    Ok(Magic)
+                } else {
+                    Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "bad magic").into())
+                }
+            }
+        }
+
+        let encoded = (1u8, [*b"MP", *b"MX"])
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+
+        assert!(matches!(
+            <(u8, [Magic; 2])>::decode_from_slice(&encoded)
+                .expect_err("The second magic is invalid")
+                .root(),
+            DecodeError::IO { offset: 3, .. }
+        ));
+    }
+
+    #[test]
+    fn decode_iter() {
+        let sessions: Vec<_> = (0..100u32).map(|i| (i, format!("session {i}"))).collect();
+        let mut encoded = sessions
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        (7u8, 8u8)
+            .encode(&mut encoded)
+            .expect("We can always encode into to a Vec");
+
+        let mut iter = DecodeIter::<(u32, String), _>::new(encoded.as_slice())
+            .expect("The length prefix is valid");
+        assert_eq!(iter.remaining(), Some(100));
+        assert_eq!(iter.offset(), 4);
+
+        let decoded: Vec<_> = iter
+            .by_ref()
+            .collect::<Result<_, _>>()
+            .expect("All the sessions were just encoded");
+        assert_eq!(decoded, sessions);
+        assert_eq!(iter.remaining(), Some(0));
+
+        // The data after the sequence is still available.
+        let (peeked, rest) = iter.into_inner().into_parts();
+        assert_eq!(peeked, None);
+        assert_eq!(rest, [7, 8]);
+
+        let mut records = DecodeIter::<(u8, u8), _>::records(rest);
+        assert_eq!(
+            records.next().transpose().expect("The record is valid"),
+            Some((7, 8))
+        );
+        assert!(records.next().is_none());
+
+        let records = [1u16, 2, 3]
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        let decoded: Vec<u16> = DecodeIter::records(records.as_slice())
+            .collect::<Result<_, _>>()
+            .expect("All the records are valid");
+        assert_eq!(decoded, [1, 2, 3]);
+
+        let mut records = DecodeIter::<u16, _>::records(&records[..5]);
+        assert_eq!(records.by_ref().take(2).count(), 2);
+
+        let error = records
+            .next()
+            .expect("The last record is cut off")
+            .expect_err("The last record is cut off");
+        assert_eq!(
+            error.path().map(ToString::to_string).as_deref(),
+            Some("[2]")
+        );
+        assert!(matches!(error.root(), DecodeError::IO { offset: 5, .. }));
+        assert!(records.next().is_none());
+    }
+
+    #[test]
+    fn decode_iter_errors() {
+        use crate::io::Read;
+
+        let mut encoded = vec![String::from("valid"); 3]
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        // Corrupt the last byte of the second string.
+        encoded[4 + 9 + 9 - 1] = 0xFF;
+
+        let results: Vec<_> = DecodeIter::<String, _>::new(encoded.as_slice())
+            .expect("The length prefix is valid")
+            .collect();
+
+        assert_eq!(
+            results.len(),
+            2,
+            "The iteration stops after the first error"
+        );
+        assert_eq!(results[0].as_deref().ok(), Some("valid"));
+
+        let error = results[1]
+            .as_ref()
+            .expect_err("The second string is invalid UTF-8");
+        assert_eq!(
+            error.path().map(ToString::to_string).as_deref(),
+            Some("[1]")
+        );
+        assert!(matches!(
+            error.root(),
+            DecodeError::InvalidUtf8 { offset: 13, .. }
+        ));
+
+        let too_long = (MAX_ARRAY_LENGTH + 1)
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        assert!(matches!(
+            DecodeIter::<u8, _>::new(too_long.as_slice()),
+            Err(DecodeError::ArrayTooBig { offset: 0, .. })
+        ));
+
+        let options = DecodeOptions::new().with_max_allocation(16);
+        let encoded = vec![vec![0u8; 16]; 4]
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        let decoded = DecodeIter::<Vec<u8>, _>::with_options(encoded.as_slice(), options)
+            .expect("The length prefix is valid")
+            .collect::<Result<Vec<_>, _>>()
+            .expect("Every element fits into the allocation budget on its own");
+        assert_eq!(decoded.len(), 4);
+
+        let options = DecodeOptions::new().with_max_depth(1);
+        let mut iter = DecodeIter::<Vec<u8>, _>::with_options(encoded.as_slice(), options)
+            .expect("The length prefix is valid");
+        assert!(matches!(
+            iter.next()
+                .expect("The sequence isn't empty")
+                .expect_err("The elements are nested too deep")
+                .root(),
+            DecodeError::DepthLimit { limit: 1, .. }
+        ));
+
+        // Records which don't read any bytes would never reach the end of the
+        // input.
+        let mut records = DecodeIter::<(), _>::records([1u8, 2].as_slice());
+        assert!(matches!(
+            records
+                .next()
+                .expect("The input isn't empty")
+                .expect_err("The record doesn't read any bytes")
+                .root(),
+            DecodeError::InvalidValue { offset: 0, .. }
+        ));
+        assert!(records.next().is_none());
+
+        // The byte which was read ahead isn't lost.
+        let mut rest = records.into_inner();
+        let mut remaining = [0u8; 2];
+        rest.read_exact(&mut remaining)
+            .expect("The reader still contains both bytes");
+        assert_eq!(remaining, [1, 2]);
+        assert_eq!(rest.into_parts(), (None, [].as_slice()));
+    }
+
+    #[test]
+    fn encoded_sizes() {
+        fn assert_encoded_size<T: Decode + Encode>(value: T) {
+            let encoded = value
+                .encode_to_vec()
+                .expect("We can always encode into to a Vec");
+
+            assert_eq!(T::ENCODED_SIZE, Some(encoded.len()));
+        }
+
+        assert_encoded_size(5u16);
+        assert_encoded_size([1u32; 3]);
+        assert_encoded_size((1u8, 'a', true));
+        assert_encoded_size(Duration::from_secs(1));
+        assert_encoded_size(Box::new(StrictFloat(1.0f64)));
+        assert_encoded_size(());
+
+        assert_eq!(<Vec<u8>>::ENCODED_SIZE, None);
+        assert_eq!(<(u8, String)>::ENCODED_SIZE, None);
+        assert_eq!(<Option<u8>>::ENCODED_SIZE, None);
+    }
+
+    #[test]
+    fn lazy_seq() {
+        let keys: Vec<[u8; 32]> = (0..100).map(|i| [i; 32]).collect();
+        let mut encoded = keys
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+
+        let lazy = LazySeq::<[u8; 32]>::new(&encoded).expect("The sequence was just encoded");
+        assert_eq!(lazy.len(), 100);
+        assert!(!lazy.is_empty());
+        assert_eq!(
+            lazy.get(17).transpose().expect("The key is valid"),
+            Some([17; 32])
+        );
+        assert_eq!(lazy.get_encoded(99), Some(&[99u8; 32][..]));
+        assert!(lazy.get(100).is_none());
+        assert_eq!(
+            lazy.iter()
+                .rev()
+                .collect::<Result<Vec<_>, _>>()
+                .expect("All keys are valid"),
+            keys.iter().rev().copied().collect::<Vec<_>>()
+        );
+
+        encoded.push(0);
+        assert!(matches!(
+            LazySeq::<[u8; 32]>::new(&encoded),
+            Err(DecodeError::TrailingBytes { remaining: 1, .. })
+        ));
+        let (lazy, rest) =
+            LazySeq::<[u8; 32]>::from_prefix(&encoded).expect("The sequence is valid");
+        assert_eq!((lazy.len(), rest), (100, &[0u8][..]));
+
+        assert!(matches!(
+            LazySeq::<[u8; 32]>::new(&encoded[..encoded.len() - 2]),
+            Err(DecodeError::IO { .. })
+        ));
+        assert!(matches!(
+            LazySeq::<u8>::new(&u32::MAX.to_be_bytes()),
+            Err(DecodeError::ArrayTooBig { offset: 0, .. })
+        ));
+
+        let encoded = vec![1u16, 0, 3]
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        let lazy = LazySeq::<NonZeroU16>::new(&encoded).expect("The sequence was just encoded");
+        assert!(matches!(lazy.get(0), Some(Ok(_))));
+
+        let error = lazy
+            .get(1)
+            .expect("The index is in bounds")
+            .expect_err("Zero isn't a valid NonZeroU16");
+        assert_eq!(
+            error.path().map(ToString::to_string).as_deref(),
+            Some("[1]")
+        );
+        assert!(matches!(
+            error.root(),
+            DecodeError::InvalidValue { offset: 6, .. }
+        ));
+
+        let options = DecodeOptions::new()
+            .with_canonical(true)
+            .with_max_array_length(2);
+        let lazy = LazySeq::<bool>::with_options(&[0, 0, 0, 2, 1, 2], options)
+            .expect("The sequence isn't too long");
+        assert!(matches!(lazy.get(0), Some(Ok(true))));
+        assert!(matches!(
+            lazy.iter().nth(1),
+            Some(Err(DecodeError::Context { .. }))
+        ));
+        assert!(matches!(
+            lazy.get(1)
+                .expect("The index is in bounds")
+                .expect_err("2 is not a canonical bool")
+                .root(),
+            DecodeError::NonCanonicalBool {
+                value: 2,
+                offset: 5
+            }
+        ));
+        assert!(matches!(
+            LazySeq::<bool>::from_prefix_with_options(&[0, 0, 0, 3, 1, 1, 1], options),
+            Err(DecodeError::ArrayTooBig {
+                length: 3,
+                limit: 2,
+                offset: 0
+            })
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "derive")]
+    fn derive_lazy_seq() {
+        #[derive(Clone, Debug, Decode, Encode, PartialEq, Eq)]
+        struct OneTimeKey {
+            key_id: [u8; 4],
+            public_key: [u8; 32],
+        }
+
+        #[derive(Clone, Debug, Decode, Encode, PartialEq, Eq)]
+        struct Tagged(u8, OneTimeKey);
+
+        #[derive(Decode)]
+        #[allow(dead_code)]
+        struct Named {
+            name: String,
+        }
+
+        assert_eq!(OneTimeKey::ENCODED_SIZE, Some(36));
+        assert_eq!(Tagged::ENCODED_SIZE, Some(37));
+        assert_eq!(Named::ENCODED_SIZE, None);
+
+        let keys: Vec<_> = (0..100)
+            .map(|i| OneTimeKey {
+                key_id: [i; 4],
+                public_key: [i; 32],
+            })
+            .collect();
+        let encoded = keys
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+
+        let lazy = LazySeq::<OneTimeKey>::new(&encoded).expect("The sequence was just encoded");
+        assert_eq!(
+            lazy.get(42).transpose().expect("The key is valid").as_ref(),
+            Some(&keys[42])
+        );
+
+        let tagged: Vec<_> = keys.into_iter().map(|key| Tagged(1, key)).collect();
+        let encoded = tagged
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        let lazy = LazySeq::<Tagged>::new(&encoded).expect("The sequence was just encoded");
+
+        assert_eq!(
+            lazy.iter()
+                .collect::<Result<Vec<_>, _>>()
+                .expect("All the keys are valid"),
+            tagged
+        );
+    }
+
+    #[test]
+    fn decode_into() {
+        let mut value = (
+            vec![0u8; 64],
+            String::with_capacity(64),
+            Box::new([0u8; 32]),
+        );
+        let pointers = (value.0.as_ptr(), value.1.as_ptr(), value.2.as_ptr());
+
+        let new_value = (vec![1u8; 16], String::from("hello"), Box::new([2u8; 32]));
+        let encoded = new_value
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+
+        value
+            .decode_into(&mut encoded.as_slice())
+            .expect("The value was just encoded");
+
+        assert_eq!(value, new_value);
+        assert_eq!(
+            (value.0.as_ptr(), value.1.as_ptr(), value.2.as_ptr()),
+            pointers
+        );
+        assert_eq!((value.0.capacity(), value.1.capacity()), (64, 64));
+
+        let mut nested = vec![vec![0u16; 8]; 4];
+        let pointer = nested[1].as_ptr();
+        let new_nested = vec![vec![1u16; 2], vec![2u16; 8], vec![3u16; 3], vec![], vec![4]];
+        let encoded = new_nested
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+
+        nested
+            .decode_into(&mut encoded.as_slice())
+            .expect("The value was just encoded");
+        assert_eq!(nested, new_nested);
+        assert_eq!(nested[1].as_ptr(), pointer);
+
+        let mut option = Some(Box::new([0u8; 32]));
+        let pointer = option.as_ref().map(|key| key.as_ptr());
+        let encoded = Some([3u8; 32])
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+
+        option
+            .decode_into(&mut encoded.as_slice())
+            .expect("The value was just encoded");
+        assert_eq!(option.as_deref(), Some(&[3u8; 32]));
+        assert_eq!(option.as_ref().map(|key| key.as_ptr()), pointer);
+
+        let mut strings = vec![String::from("valid"); 2];
+        let encoded = [
+            [0u8, 0, 0, 2],
+            [0, 0, 0, 1],
+            [b'a', 0, 0, 0],
+            [1, 0xFF, 0, 0],
+        ]
+        .concat();
+        let error = strings
+            .decode_into(&mut &encoded[..14])
+            .expect_err("The second string is invalid UTF-8");
+
+        assert_eq!(
+            error.path().map(ToString::to_string).as_deref(),
+            Some("[1]")
+        );
+        assert!(matches!(
+            error.root(),
+            DecodeError::InvalidUtf8 { offset: 9, .. }
+        ));
+
+        let mut decoder = Decoder::with_options(
+            encoded.as_slice(),
+            DecodeOptions::new().with_max_array_length(1),
+        );
+        assert!(matches!(
+            decoder.decode_into(&mut strings),
+            Err(DecodeError::ArrayTooBig { length: 2, .. })
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "derive")]
+    fn derive_decode_into() {
+        #[derive(Debug, Decode, Encode, PartialEq, Eq)]
+        struct Account {
+            #[secret]
+            private_key: Box<[u8; 32]>,
+            one_time_keys: Vec<[u8; 32]>,
+            session: Session,
+        }
+
+        #[derive(Debug, Decode, Encode, PartialEq, Eq)]
+        struct Chain(Vec<u8>, u32);
+
+        #[derive(Debug, Decode, Encode, PartialEq, Eq)]
+        enum Session {
+            Sending(Chain),
+            Receiving(Vec<Chain>),
+        }
+
+        let mut account = Account {
+            private_key: Box::new([0u8; 32]),
+            one_time_keys: Vec::with_capacity(100),
+            session: Session::Sending(Chain(vec![0; 32], 0)),
+        };
+        let pointers = (account.private_key.as_ptr(), account.one_time_keys.as_ptr());
+
+        let Session::Sending(Chain(chain_key, _)) = &account.session else {
+            unreachable!("The session is a sending session")
+        };
+        let chain_pointer = chain_key.as_ptr();
+
+        let new_account = Account {
+            private_key: Box::new([1u8; 32]),
+            one_time_keys: vec![[2u8; 32]; 50],
+            session: Session::Sending(Chain(vec![3; 32], 7)),
+        };
+        let encoded = new_account
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+
+        account
+            .decode_into(&mut encoded.as_slice())
+            .expect("The account was just encoded");
+
+        assert_eq!(account, new_account);
+        assert_eq!(
+            (account.private_key.as_ptr(), account.one_time_keys.as_ptr()),
+            pointers
+        );
+
+        let Session::Sending(Chain(chain_key, _)) = &account.session else {
+            unreachable!("The session is still a sending session")
+        };
+        assert_eq!(chain_key.as_ptr(), chain_pointer);
+
+        let new_account = Account {
+            session: Session::Receiving(vec![Chain(vec![4; 32], 1)]),
+            ..new_account
+        };
+        let encoded = new_account
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+
+        account
+            .decode_into(&mut encoded.as_slice())
+            .expect("The account was just encoded");
+        assert_eq!(account, new_account);
+
+        let mut corrupted = encoded.clone();
+        // The session is made up of the variant number, the length of the
+        // `Vec` and a single chain of 40 bytes.
+        let variant = corrupted.len() - 45;
+        corrupted[variant] = 5;
+
+        let error = account
+            .decode_into(&mut corrupted.as_slice())
+            .expect_err("The enum variant is invalid");
+        assert_eq!(
+            error.path().map(ToString::to_string).as_deref(),
+            Some("Account.session")
+        );
+        assert!(matches!(
+            error.root(),
+            DecodeError::UnknownEnumVariant {
+                type_name: "Session",
+                variant: 5,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn decode_options() {
+        let encoded = vec![vec![[0u8; 1024]; 8]; 8]
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+
+        let decode = |options| {
+            let mut decoder = Decoder::with_options(encoded.as_slice(), options);
+            decoder.decode::<Vec<Vec<[u8; 1024]>>>()
+        };
+
+        decode(DecodeOptions::new()).expect("The default limits allow this value");
+
+        assert!(matches!(
+            decode(DecodeOptions::new().with_max_array_length(7))
+                .expect_err("The arrays are too long")
+                .root(),
+            DecodeError::ArrayTooBig { length: 8, .. }
+        ));
+        assert!(matches!(
+            decode(DecodeOptions::new().with_max_allocation(32 * 1024))
+                .expect_err("The value is too big")
+                .root(),
+            DecodeError::AllocationLimit { .. }
+        ));
+        assert!(matches!(
+            decode(DecodeOptions::new().with_max_depth(1))
+                .expect_err("The value is nested too deep")
+                .root(),
+            DecodeError::DepthLimit { limit: 1, .. }
+        ));
+        decode(
+            DecodeOptions::new()
+                .with_max_array_length(8)
+                .with_max_allocation(64 * 1024 + 8 * size_of::<Vec<[u8; 1024]>>())
+                .with_max_depth(2),
+        )
+        .expect("The value fits exactly within the limits");
+    }
+
+    #[test]
+    fn max_allocation_overflow() {
+        let mut decoder = Decoder::with_options(
+            [].as_slice(),
+            DecodeOptions::new().with_max_allocation(usize::MAX),
+        );
+
+        assert!(matches!(
+            decoder.claim_allocation::<u64>(usize::MAX, 0),
+            Err(DecodeError::AllocationLimit { .. })
+        ));
+        decoder
+            .claim_allocation::<u8>(usize::MAX, 0)
+            .expect("The whole budget can be claimed");
+        assert!(matches!(
+            decoder.claim_allocation::<u8>(1, 7),
+            Err(DecodeError::AllocationLimit { offset: 7, .. })
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "derive")]
+    fn derive_decode_options() {
+        #[derive(Encode, Decode, PartialEq, Debug)]
+        struct Node {
+            value: Vec<u8>,
+            next: Option<Box<Node>>,
+        }
+
+        let mut node = Node {
+            value: vec![1; 100],
+            next: None,
+        };
+
+        for _ in 0..10 {
+            node = Node {
+                value: vec![1; 100],
+                next: Some(Box::new(node)),
+            };
+        }
+
+        let encoded = node
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        let decode = |options| Decoder::with_options(encoded.as_slice(), options).decode::<Node>();
+
+        assert_eq!(
+            decode(DecodeOptions::new()).expect("The default limits allow this value"),
+            node
+        );
+        assert!(matches!(
+            decode(DecodeOptions::new().with_max_depth(10))
+                .expect_err("The value is nested too deep")
+                .root(),
+            DecodeError::DepthLimit { limit: 10, .. }
+        ));
+        assert!(matches!(
+            decode(DecodeOptions::new().with_max_allocation(1000))
+                .expect_err("The value is too big")
+                .root(),
+            DecodeError::AllocationLimit { limit: 1000, .. }
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "derive")]
+    fn derive_error_paths() {
+        #[derive(Debug, Decode, Encode)]
+        struct OneTimeKey {
+            key_id: u32,
+            public_key: String,
+        }
+
+        #[derive(Debug, DecodeBorrowed)]
+        struct BorrowedKey<'a> {
+            #[allow(dead_code)]
+            key_id: u32,
+            #[allow(dead_code)]
+            public_key: &'a str,
+        }
+
+        #[derive(Debug, Decode, Encode)]
+        struct Account {
+            name: String,
+            one_time_keys: Vec<OneTimeKey>,
+        }
+
+        #[derive(Debug, Decode, Encode)]
+        struct Keys(u8, [OneTimeKey; 2]);
+
+        #[derive(Debug, Decode, Encode)]
+        enum Pickle {
+            Account(Account),
+            Keys(Keys),
+        }
+
+        let key = |key_id| OneTimeKey {
+            key_id,
+            public_key: String::from("key"),
+        };
+        let account = Account {
+            name: String::from("alice"),
+            one_time_keys: (0..20).map(key).collect(),
+        };
+
+        let mut encoded = Pickle::Account(account)
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+
+        // Replace the last byte of the public key of the 18th one-time key with
+        // invalid UTF-8.
+        let offset = 1 + 9 + 4 + 18 * 11 - 1;
+        encoded[offset] = 0xFF;
+
+        let error =
+            Pickle::decode_from_slice(&encoded).expect_err("The public key is invalid UTF-8");
+        let path = error
+            .path()
+            .expect("Errors of derived types contain a path");
+
+        assert_eq!(
+            path.to_string(),
+            "Pickle::Account.one_time_keys[17].public_key"
+        );
+        assert_eq!(path.type_name(), Some("Pickle"));
+        assert_eq!(
+            path.segments(),
+            [
+                PathSegment::Variant("Account"),
+                PathSegment::Field("one_time_keys"),
+                PathSegment::Index(17),
+                PathSegment::Field("public_key"),
+            ]
+        );
+        assert!(matches!(error.root(), DecodeError::InvalidUtf8 { .. }));
+        // The offset points at the length prefix of the three byte public key.
+        assert_eq!(error.offset(), offset - 2 - 4);
+
+        let encoded = Keys(1, [key(1), key(2)])
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+
+        let error = Keys::decode_from_slice(&encoded[..encoded.len() - 1])
+            .expect_err("The second public key is truncated");
+        assert_eq!(
+            error.path().map(ToString::to_string).as_deref(),
+            Some("Keys.1[1].public_key")
+        );
+
+        let error = BorrowedKey::decode_from_borrowed_slice(&encoded[1..5])
+            .expect_err("The public key is missing");
+        assert_eq!(
+            error.path().map(ToString::to_string).as_deref(),
+            Some("BorrowedKey.public_key")
+        );
+
+        let error = <[u8; 4]>::decode_from_slice(&[1, 2]).expect_err("The array is truncated");
+        assert!(error.path().is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "derive")]
+    fn unknown_enum_variant() {
+        #[derive(Debug, Decode)]
+        #[allow(dead_code)]
+        enum Key {
+            Curve25519([u8; 32]),
+            Ed25519([u8; 32]),
+        }
+
+        let encoded = [2u8; 33];
+        let error = Key::decode_from_slice(&encoded).expect_err("Variant 2 doesn't exist");
+
+        assert!(matches!(
+            &error,
+            DecodeError::UnknownEnumVariant {
+                type_name: "Key",
+                variant: 2,
+                variants,
+                offset: 0,
+            } if *variants == (0..2)
+        ));
+        assert_eq!(
+            error.to_string(),
+            "The variant number 2 at byte offset 0 is not a valid variant of the enum Key, \
+             expected a number in the range 0..2"
+        );
+    }
+
+    #[test]
+    fn custom_errors() {
+        #[derive(Debug)]
+        #[allow(dead_code)]
+        struct Even(u32);
+
+        impl Decode for Even {
+            fn decode(reader: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+                let value = u32::decode(reader)?;
+
+                if value % 2 == 0 {
+                    Ok(Even(value))
+                } else {
+                    Err(DecodeError::custom(format!("{value} is not even")))
+                }
+            }
+        }
+
+        let encoded = [2u32, 3]
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        let error = <[Even; 2]>::decode_from_slice(&encoded).expect_err("3 is not even");
+
+        // Errors of hand-written implementations point to the start of the
+        // value.
+        assert!(matches!(
+            error.root(),
+            DecodeError::Custom { offset: 4, .. }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Failed to decode [1]: Decoding the value at byte offset 4 failed: 3 is not even"
+        );
+    }
+
+    #[test]
+    fn canonical_decoding() {
+        let canonical = DecodeOptions::new().with_canonical(true);
+
+        assert!(bool::decode_from_slice(&[2]).expect("Any nonzero byte is true by default"));
+
+        let mut decoder = Decoder::with_options([1u8, 0, 2].as_slice(), canonical);
+        assert!(decoder.decode::<bool>().expect("1 is a canonical bool"));
+        assert!(!decoder.decode::<bool>().expect("0 is a canonical bool"));
+        assert!(matches!(
+            decoder
+                .decode::<bool>()
+                .expect_err("2 is not a canonical bool"),
+            DecodeError::NonCanonicalBool {
+                value: 2,
+                offset: 2
+            }
+        ));
+
+        let mut encoded = vec![true, false, true]
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        encoded[6] = 0xFF;
+
+        let mut decoder = Decoder::with_options(encoded.as_slice(), canonical);
+        let error = decoder
+            .decode::<Vec<bool>>()
+            .expect_err("0xFF is not a canonical bool");
+        assert_eq!(
+            error.path().map(ToString::to_string).as_deref(),
+            Some("[2]")
+        );
+        assert!(matches!(
+            error.root(),
+            DecodeError::NonCanonicalBool { value: 0xFF, .. }
+        ));
+    }
+
+    #[test]
+    fn is_canonical_check() {
+        /// A type whose hand-written `Decode` implementation accepts many
+        /// encodings of the same value.
+        #[derive(Debug)]
+        struct Parity(bool);
+
+        impl Encode for Parity {
+            fn encode(&self, writer: &mut impl std::io::Write) -> Result<usize, EncodeError> {
+                u8::from(self.0).encode(writer)
+            }
+        }
+
+        impl Decode for Parity {
+            fn decode(reader: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+                Ok(Parity(u8::decode(reader)? % 2 == 1))
+            }
+        }
+
+        let encoded = (true, String::from("Hello"), BTreeMap::from([(1u8, 2u8)]))
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        assert!(is_canonical::<(bool, String, BTreeMap<u8, u8>)>(&encoded));
+
+        assert!(is_canonical::<bool>(&[0]));
+        assert!(!is_canonical::<bool>(&[2]));
+        assert!(!is_canonical::<bool>(&[1, 0]));
+        assert!(!is_canonical::<bool>(&[]));
+        assert!(!is_canonical::<u32>(&[0, 0, 1]));
+        assert!(!is_canonical::<Option<u8>>(&[2, 0]));
+        assert!(is_canonical::<f64>(&1.5f64.to_bits().to_be_bytes()));
+        assert!(is_canonical::<f64>(
+            &StrictFloat::<f64>::CANONICAL_NAN_BITS.to_be_bytes()
+        ));
+        assert!(!is_canonical::<f64>(
+            &f64::NAN.to_bits().wrapping_add(1).to_be_bytes()
+        ));
+
+        let unsorted = vec![(2u8, 0u8), (1, 0)]
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        assert!(!is_canonical::<BTreeMap<u8, u8>>(&unsorted));
+
+        assert!(is_canonical::<Parity>(&[1]));
+        assert!(!is_canonical::<Parity>(&[3]));
+    }
+
+    #[test]
+    #[cfg(feature = "derive")]
+    fn derive_canonical_decoding() {
+        #[derive(Debug, Decode, Encode, PartialEq, Eq)]
+        struct Settings {
+            verified: bool,
+            flags: [bool; 4],
+        }
+
+        let settings = Settings {
+            verified: true,
+            flags: [true, false, false, true],
+        };
+        let mut encoded = settings
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        assert!(is_canonical::<Settings>(&encoded));
+
+        encoded[3] = 7;
+        assert!(!is_canonical::<Settings>(&encoded));
+
+        let options = DecodeOptions::new().with_canonical(true);
+        let mut decoder = Decoder::with_options(encoded.as_slice(), options);
+        let error = decoder
+            .decode::<Settings>()
+            .expect_err("7 is not a canonical bool");
+        assert_eq!(
+            error.path().map(ToString::to_string).as_deref(),
+            Some("Settings.flags[2]")
+        );
+        assert!(matches!(
+            error.root(),
+            DecodeError::NonCanonicalBool {
+                value: 7,
+                offset: 3
+            }
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "sans-io")]
+    fn push_decoder() {
+        let value = (
+            vec![String::from("Hello"); 3],
+            [7u32; 4],
+            Some(Duration::new(5, 10)),
+        );
+        let encoded = value
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+
+        let mut decoder = PushDecoder::new();
+        let (last, chunks) = encoded.split_last().expect("The encoding isn't empty");
+
+        for byte in chunks {
+            match decoder
+                .push(&[*byte])
+                .expect("A partial value isn't an error")
+            {
+                DecodeProgress::NeedMoreBytes(wanted) => assert!(wanted > 0),
+                DecodeProgress::Done(_) => panic!("The value is done too early"),
+            }
+        }
+
+        assert_eq!(
+            decoder
+                .push(&[*last])
+                .expect("Decoding a freshly encoded value always works"),
+            DecodeProgress::Done(value)
+        );
+
+        // Bytes pushed after a value are the start of the next one.
+        let mut decoder = PushDecoder::<u16>::new();
+        assert_eq!(
+            decoder
+                .push(&[0, 1, 0, 2, 0])
+                .expect("The first value is complete"),
+            DecodeProgress::Done(1)
+        );
+        assert_eq!(
+            decoder.push(&[]).expect("The second value is complete"),
+            DecodeProgress::Done(2)
+        );
+        assert_eq!(
+            decoder.push(&[]).expect("A partial value isn't an error"),
+            DecodeProgress::NeedMoreBytes(1)
+        );
+        assert_eq!(*decoder.into_buffered(), Vec::<u8>::new());
+
+        let mut decoder = PushDecoder::<u16>::new();
+        assert_eq!(
+            decoder
+                .push(&[0, 1, 0])
+                .expect("The first value is complete"),
+            DecodeProgress::Done(1)
+        );
+        assert_eq!(
+            decoder
+                .push(&[2, 0, 3, 0])
+                .expect("The second value is complete"),
+            DecodeProgress::Done(2)
+        );
+        assert_eq!(
+            decoder.push(&[]).expect("The third value is complete"),
+            DecodeProgress::Done(3)
+        );
+        assert_eq!(*decoder.into_buffered(), vec![0]);
+    }
+
+    #[test]
+    #[cfg(feature = "sans-io")]
+    fn push_decoder_errors() {
+        let encoded = vec![1u8; 100]
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+
+        let options = DecodeOptions::new().with_max_array_length(10);
+        let mut decoder = PushDecoder::<Vec<u8>>::with_options(options);
+        assert_eq!(
+            decoder
+                .push(&encoded[..2])
+                .expect("A partial length isn't an error"),
+            DecodeProgress::NeedMoreBytes(2)
+        );
+        assert!(matches!(
+            decoder
+                .push(&encoded[2..])
+                .expect_err("The array is too long"),
+            DecodeError::ArrayTooBig {
+                length: 100,
+                limit: 10,
+                offset: 0
+            }
+        ));
+
+        let mut decoder = PushDecoder::<Vec<char>>::new();
+        let encoded = [0, 0, 0, 2, 0, 0, 0, 0x61, 0, 0, 0xD8];
+        assert_eq!(
+            decoder
+                .push(&encoded)
+                .expect("A partial value isn't an error"),
+            DecodeProgress::NeedMoreBytes(1)
+        );
+        let error = decoder.push(&[0]).expect_err("The character is invalid");
+        assert_eq!(
+            error.path().map(ToString::to_string).as_deref(),
+            Some("[1]")
+        );
+        assert!(matches!(
+            error.root(),
+            DecodeError::InvalidChar {
+                value: 0xD800,
+                offset: 8
+            }
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "sans-io")]
+    fn push_decoder_does_not_reparse() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static DECODED: AtomicUsize = AtomicUsize::new(0);
+
+        /// A string which counts how often it has been decoded.
+        #[derive(Debug, PartialEq, Eq)]
+        struct Counted(String);
+
+        impl Decode for Counted {
+            fn decode(reader: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+                Ok(Counted(String::decode(reader)?))
+            }
+        }
+
+        impl AsyncDecode for Counted {
+            fn decode_async_with<'a, R: AsyncReader + 'a>(
+                decoder: &'a mut AsyncDecoder<R>,
+            ) -> DecodeFuture<'a, Self> {
+                Box::pin(async move {
+                    DECODED.fetch_add(1, Ordering::SeqCst);
+                    Ok(Counted(decoder.decode().await?))
+                })
+            }
+        }
+
+        let encoded = vec!["a", "bc", "def"]
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+
+        let mut decoder = PushDecoder::<Vec<Counted>>::new();
+        let mut decoded = None;
+
+        for byte in &encoded {
+            if let DecodeProgress::Done(value) = decoder
+                .push(&[*byte])
+                .expect("Decoding a freshly encoded value always works")
+            {
+                decoded = Some(value);
+            }
+        }
+
+        assert_eq!(
+            decoded.expect("The value is done after the last byte"),
+            ["a", "bc", "def"].map(|s| Counted(s.to_owned()))
+        );
+        assert_eq!(DECODED.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    #[cfg(all(feature = "derive", feature = "sans-io"))]
+    fn derive_push_decoder() {
+        #[derive(Debug, Decode, AsyncDecode, Encode, PartialEq, Eq)]
+        struct OneTimeKey {
+            key_id: u32,
+            public_key: [u8; 32],
+        }
+
+        #[derive(Debug, Decode, AsyncDecode, Encode, PartialEq, Eq)]
+        enum Pickle {
+            Keys(Vec<OneTimeKey>),
+            Name(String),
+        }
+
+        let pickle = Pickle::Keys(
+            (0..10)
+                .map(|key_id| OneTimeKey {
+                    key_id,
+                    public_key: [key_id as u8; 32],
+                })
+                .collect(),
+        );
+        let mut encoded = pickle
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        let name = Pickle::Name(String::from("alice"));
+        encoded.extend(
+            name.encode_to_vec()
+                .expect("We can always encode into to a Vec"),
+        );
+
+        let mut decoder = PushDecoder::<Pickle>::new();
+        let mut decoded = Vec::new();
+
+        for chunk in encoded.chunks(7) {
+            if let DecodeProgress::Done(value) = decoder
+                .push(chunk)
+                .expect("Decoding a freshly encoded value always works")
+            {
+                decoded.push(value);
+            }
+        }
+
+        while let DecodeProgress::Done(value) = decoder
+            .push(&[])
+            .expect("Decoding a freshly encoded value always works")
+        {
+            decoded.push(value);
+        }
+
+        assert_eq!(decoded, [pickle, name]);
+    }
+
+    #[test]
+    fn short_writes() {
+        /// A writer which is interrupted on every other call, and accepts a
+        /// single byte otherwise.
+        struct TricklingWriter {
+            written: Vec<u8>,
+            interrupt: bool,
+        }
+
+        impl std::io::Write for TricklingWriter {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.interrupt = !self.interrupt;
+
+                if self.interrupt {
+                    return Err(std::io::ErrorKind::Interrupted.into());
+                }
+
+                let written = buf.len().min(1);
+                self.written.extend_from_slice(&buf[..written]);
+
+                Ok(written)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let value = (5u8, true, [1u8, 2, 3], String::from("Hello"), Some(7u8));
+        let mut writer = TricklingWriter {
+            written: Vec::new(),
+            interrupt: false,
+        };
+
+        let size = value
+            .encode(&mut writer)
+            .expect("Short and interrupted writes are retried");
+        assert_eq!(size, writer.written.len());
+        assert_eq!(
+            writer.written,
+            value
+                .encode_to_vec()
+                .expect("We can always encode into to a Vec")
+        );
+    }
+
+    /// A non-blocking writer which accepts at most `limit` bytes per call, and
+    /// would block once it has accepted `budget` bytes.
+    #[cfg(feature = "sans-io")]
+    struct NonBlockingWriter {
+        written: Vec<u8>,
+        limit: usize,
+        budget: usize,
+    }
+
+    #[cfg(feature = "sans-io")]
+    impl std::io::Write for NonBlockingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let written = buf.len().min(self.limit).min(self.budget);
+
+            if written == 0 && !buf.is_empty() {
+                return Err(std::io::ErrorKind::WouldBlock.into());
+            }
+
+            self.budget -= written;
+            self.written.extend_from_slice(&buf[..written]);
+
+            Ok(written)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "sans-io")]
+    fn secret_buffer() {
+        use crate::async_io::SecretBuffer;
+
+        let mut buffer = SecretBuffer::with_capacity(4);
+        buffer.extend_from_slice(&[1, 2, 3]);
+        buffer.consume(2);
+        assert_eq!(&*buffer, &[3]);
+
+        // The consumed bytes make room for new ones, growing moves the rest.
+        buffer.extend_from_slice(&[4, 5, 6]);
+        buffer.extend_from_slice(&[7]);
+        assert_eq!(&*buffer, &[3, 4, 5, 6, 7]);
+
+        buffer.consume(1);
+        assert_eq!(*buffer.into_unconsumed(), vec![4, 5, 6, 7]);
+
+        let mut buffer = SecretBuffer::default();
+        buffer.extend_from_slice(&[1]);
+        buffer.clear();
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "sans-io")]
+    fn pull_encoder() {
+        let value = (
+            String::from("Hello"),
+            (0..5_000u32).collect::<Vec<_>>(),
+            vec![vec![1u8; 100]; 50],
+            BTreeMap::from([(1u8, Some(String::from("a"))), (2, None)]),
+        );
+        let expected = value
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+
+        let mut encoder = PullEncoder::with_capacity(&value, 10);
+        let mut encoded = Vec::new();
+        let mut buf = [0u8; 7];
+
+        loop {
+            let pulled = encoder
+                .pull(&mut buf)
+                .expect("We can always encode into a buffer");
+
+            if pulled == 0 {
+                break;
+            }
+
+            encoded.extend_from_slice(&buf[..pulled]);
+        }
+
+        assert_eq!(encoded, expected);
+        assert!(encoder.is_done());
+
+        let mut encoder = PullEncoder::new(&value);
+        let mut encoded = vec![0u8; expected.len() + 10];
+        assert_eq!(
+            encoder
+                .pull(&mut encoded)
+                .expect("We can always encode into a buffer"),
+            expected.len()
+        );
+        assert_eq!(encoded[..expected.len()], expected);
+        assert!(encoder.is_done());
+    }
+
+    #[test]
+    #[cfg(feature = "sans-io")]
+    fn pull_encoder_would_block() {
+        let value = vec![String::from("Hello world"); 1_000];
+        let expected = value
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+
+        let mut writer = NonBlockingWriter {
+            written: Vec::new(),
+            limit: 5,
+            budget: 0,
+        };
+        let mut encoder = PullEncoder::with_capacity(&value, 64);
+        let mut blocked = 0;
+
+        loop {
+            writer.budget = 333;
+
+            match encoder
+                .write_to(&mut writer)
+                .expect("The writer only ever blocks")
+            {
+                EncodeProgress::WouldBlock => blocked += 1,
+                EncodeProgress::Done(size) => {
+                    assert_eq!(size, expected.len());
+                    break;
+                }
+            }
+        }
+
+        assert_eq!(writer.written, expected);
+        assert_eq!(blocked, expected.len() / 333);
+    }
+
+    #[test]
+    #[cfg(feature = "sans-io")]
+    fn pull_encoder_errors() {
+        let value = (1u8, vec![0u8; MAX_ARRAY_LENGTH + 1]);
+        let mut encoder = PullEncoder::new(&value);
+        assert!(matches!(
+            encoder.pull(&mut [0u8; 16]),
+            Err(EncodeError::ArrayTooBig(_))
+        ));
+
+        let mut buffer = [0u8; 4];
+        let mut writer = &mut buffer[..];
+        let mut encoder = PullEncoder::new("Hello");
+        assert!(matches!(
+            encoder.write_to(&mut writer).expect_err("The buffer is too small"),
+            EncodeError::IO(e) if e.kind() == std::io::ErrorKind::WriteZero
+        ));
+    }
+
+    #[test]
+    #[cfg(all(feature = "derive", feature = "sans-io"))]
+    fn derive_pull_encoder() {
+        #[derive(Debug, Decode, Encode, AsyncEncode, PartialEq, Eq)]
+        struct OneTimeKey {
+            key_id: u32,
+            public_key: [u8; 32],
+        }
+
+        #[derive(Debug, Decode, Encode, AsyncEncode, PartialEq, Eq)]
+        struct Wrapper<T>(u8, T);
+
+        #[derive(Debug, Decode, Encode, AsyncEncode, PartialEq, Eq)]
+        enum Pickle {
+            Keys(Vec<OneTimeKey>),
+            Wrapper(Wrapper<String>),
+        }
+
+        let keys = Pickle::Keys(
+            (0..10)
+                .map(|key_id| OneTimeKey {
+                    key_id,
+                    public_key: [key_id as u8; 32],
+                })
+                .collect(),
+        );
+        let wrapper = Pickle::Wrapper(Wrapper(1, String::from("bob")));
+
+        for pickle in [keys, wrapper] {
+            let mut writer = NonBlockingWriter {
+                written: Vec::new(),
+                limit: 3,
+                budget: 0,
+            };
+            let mut encoder = PullEncoder::with_capacity(&pickle, 16);
+
+            while encoder
+                .write_to(&mut writer)
+                .expect("The writer only ever blocks")
+                == EncodeProgress::WouldBlock
+            {
+                writer.budget = 50;
+            }
+
+            assert_eq!(
+                writer.written,
+                pickle
+                    .encode_to_vec()
+                    .expect("We can always encode into to a Vec")
+            );
+
+            let decoded = Pickle::decode_from_slice(&writer.written)
+                .expect("Decoding a freshly encoded value always works");
+            assert_eq!(pickle, decoded);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "bytes")]
+    fn bytes_buffers() {
+        use bytes::{Buf, Bytes, BytesMut};
+
+        encode_cycle!(Bytes::from_static(b"Hello world") => Bytes);
+        encode_cycle!(BytesMut::from("Hello world") => BytesMut);
+        encode_length_check!(Bytes::from_static(b"Hello world"));
+
+        assert_eq!(
+            Bytes::from_static(b"Hello")
+                .encode_to_vec()
+                .expect("We can always encode into to a Vec"),
+            b"Hello"
+                .to_vec()
+                .encode_to_vec()
+                .expect("We can always encode into to a Vec"),
+        );
+
+        let mut buf = BytesMut::new();
+        let size = (1u8, String::from("Hello"))
+            .encode_to_buf(&mut buf)
+            .expect("We can always encode into to a BytesMut");
+        let size = size
+            + 2u16
+                .encode_to_buf(&mut buf)
+                .expect("We can always encode into to a BytesMut");
+        assert_eq!(size, buf.len());
+
+        // Decoding from a `Buf` advances it past the decoded value, even if
+        // the value is split across chunks.
+        let (first, second) = buf.split_at(3);
+        let mut chain = first.chain(second);
+        let decoded = <(u8, String)>::decode_from_buf(&mut chain)
+            .expect("Decoding a freshly encoded value always works");
+        assert_eq!(decoded, (1, String::from("Hello")));
+        assert_eq!(
+            u16::decode_from_buf(&mut chain).expect("The second value follows the first one"),
+            2
+        );
+        assert!(!chain.has_remaining());
+
+        let options = DecodeOptions::new().with_canonical(true);
+        assert!(bool::decode_from_buf(&mut [2u8].as_slice()).expect("Any nonzero byte is true"));
+        assert!(matches!(
+            bool::decode_from_buf_with_options(&mut [2u8].as_slice(), options),
+            Err(DecodeError::NonCanonicalBool {
+                value: 2,
+                offset: 0
+            })
+        ));
+        assert!(matches!(
+            bool::decode_from_bytes_with_options(&mut Bytes::from_static(&[2]), options),
+            Err(DecodeError::NonCanonicalBool {
+                value: 2,
+                offset: 0
+            })
+        ));
+
+        let mut too_small = [0u8; 4];
+        assert!(matches!(
+            Bytes::from_static(b"Hello").encode_to_buf(&mut too_small.as_mut_slice()),
+            Err(EncodeError::IO(_))
+        ));
+    }
+
+    #[test]
+    #[cfg(all(feature = "bytes", feature = "derive"))]
+    fn bytes_zero_copy() {
+        use bytes::Bytes;
+
+        #[derive(Debug, Decode, Encode, PartialEq, Eq)]
+        struct Message {
+            id: u32,
+            payload: Bytes,
+            signatures: Vec<Bytes>,
+        }
+
+        let message = Message {
+            id: 5,
+            payload: Bytes::from_static(b"Hello world"),
+            signatures: vec![Bytes::from_static(&[1; 64]), Bytes::from_static(&[2; 64])],
+        };
+        let mut encoded = message
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        encoded.extend([0xFF; 3]);
+
+        let input = Bytes::from(encoded);
+        let range = input.as_ptr_range();
+        let mut remaining = input.clone();
+
+        let decoded = Message::decode_from_bytes(&mut remaining)
+            .expect("Decoding a freshly encoded value always works");
+        assert_eq!(decoded, message);
+        assert_eq!(remaining, Bytes::from_static(&[0xFF; 3]));
+
+        // The decoded buffers point into the input.
+        assert!(range.contains(&decoded.payload.as_ptr()));
+        for signature in &decoded.signatures {
+            assert!(range.contains(&signature.as_ptr()));
+        }
+
+        let decoded = Message::decode_from_buf(&mut input.clone())
+            .expect("Decoding a freshly encoded value always works");
+        assert_eq!(decoded, message);
+        assert!(!range.contains(&decoded.payload.as_ptr()));
+
+        // Truncated input fails instead of handing out a shorter slice.
+        let mut truncated = input.slice(..20);
+        assert!(matches!(
+            Message::decode_from_bytes(&mut truncated)
+                .expect_err("The input is truncated")
+                .root(),
+            DecodeError::IO { offset: 20, .. }
+        ));
+    }
+
+    /// Run a future to completion on the current thread.
+    #[cfg(any(feature = "tokio", feature = "futures-io"))]
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        use std::{
+            task::{Context, Poll, Wake, Waker},
+            thread::Thread,
+        };
+
+        struct ThreadWaker(Thread);
+
+        impl Wake for ThreadWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        let mut context = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+
+        loop {
+            match future.as_mut().poll(&mut context) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    /// A reader which hands out a single byte at a time, and is pending
+    /// before every byte.
+    #[cfg(feature = "futures-io")]
+    struct TricklingReader<'a> {
+        bytes: &'a [u8],
+        ready: bool,
+    }
+
+    #[cfg(feature = "futures-io")]
+    impl futures_io::AsyncRead for TricklingReader<'_> {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &mut [u8],
+        ) -> std::task::Poll<std::io::Result<usize>> {
+            if !self.ready {
+                self.ready = true;
+                cx.waker().wake_by_ref();
+
+                return std::task::Poll::Pending;
+            }
+
+            self.ready = false;
+
+            let read = buf.len().min(self.bytes.len()).min(1);
+            buf[..read].copy_from_slice(&self.bytes[..read]);
+            self.bytes = &self.bytes[read..];
+
+            std::task::Poll::Ready(Ok(read))
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "tokio")]
+    fn async_encode_cycle() {
+        macro_rules! async_encode_cycle {
+            ($value:expr => $type:ty) => {
+                let value: $type = $value;
+
+                let mut encoded = Vec::new();
+                let size = block_on(value.encode_tokio(&mut encoded))
+                    .expect("We can always encode into to a Vec");
+                assert_eq!(size, encoded.len());
+                assert_eq!(
+                    encoded,
+                    value
+                        .encode_to_vec()
+                        .expect("We can always encode into to a Vec")
+                );
+
+                let decoded = block_on(<$type>::decode_tokio(&mut encoded.as_slice()))
+                    .expect("Decoding a freshly encoded value always works");
+                assert_eq!(value, decoded);
+            };
+        }
+
+        async_encode_cycle!(10 => u8);
+        async_encode_cycle!(-10 => i64);
+        async_encode_cycle!(u128::MAX => u128);
+        async_encode_cycle!(true => bool);
+        async_encode_cycle!(1.5 => f64);
+        async_encode_cycle!('🦀' => char);
+        async_encode_cycle!(Duration::new(5, 10) => Duration);
+        async_encode_cycle!(NonZeroU32::new(5).expect("5 is not zero") => NonZeroU32);
+        async_encode_cycle!([1u8; 32] => [u8; 32]);
+        async_encode_cycle!([String::from("a"), String::from("b")] => [String; 2]);
+        async_encode_cycle!((0..10_000).collect() => Vec<u32>);
+        async_encode_cycle!(vec![vec![1u8, 2], vec![3]] => Vec<Vec<u8>>);
+        async_encode_cycle!(String::from("Hello world") => String);
+        async_encode_cycle!(Arc::from("Hello world") => Arc<str>);
+        async_encode_cycle!(Box::new([1u8, 2, 3]) => Box<[u8]>);
+        async_encode_cycle!(Box::new([7u8; 32]) => Box<[u8; 32]>);
+        async_encode_cycle!(Box::new(String::from("a")) => Box<String>);
+        async_encode_cycle!(VecDeque::from([1u16, 2, 3]) => VecDeque<u16>);
+        async_encode_cycle!(BTreeSet::from([3u8, 1, 2]) => BTreeSet<u8>);
+        async_encode_cycle!(HashMap::from([(1u8, String::from("a"))]) => HashMap<u8, String>);
+        async_encode_cycle!(Some(Some(5u8)) => Option<Option<u8>>);
+        async_encode_cycle!(None => Option<String>);
+        async_encode_cycle!((1u8, String::from("a"), vec![1u32]) => (u8, String, Vec<u32>));
+        async_encode_cycle!(Cow::Owned(String::from("a")) => Cow<'_, str>);
+        async_encode_cycle!(StrictFloat(0.5) => StrictFloat<f32>);
+        #[cfg(feature = "bytes")]
+        async_encode_cycle!(bytes::Bytes::from_static(b"Hello") => bytes::Bytes);
+        #[cfg(feature = "bytes")]
+        async_encode_cycle!(bytes::BytesMut::from("Hello") => bytes::BytesMut);
+    }
+
+    #[test]
+    #[cfg(feature = "tokio")]
+    fn async_decode_options() {
+        fn decode<T: AsyncDecode>(
+            encoded: &[u8],
+            options: DecodeOptions,
+        ) -> Result<T, DecodeError> {
+            let mut decoder = AsyncDecoder::with_options(TokioReader(encoded), options);
+            block_on(decoder.decode())
+        }
+
+        let encoded = vec![0u8; 100]
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        let options = DecodeOptions::new().with_max_array_length(99);
+        assert!(matches!(
+            decode::<Vec<u8>>(&encoded, options).expect_err("The array is too long"),
+            DecodeError::ArrayTooBig {
+                length: 100,
+                limit: 99,
+                offset: 0
+            }
+        ));
+
+        let encoded = vec![vec![0u32; 100]; 3]
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        let options = DecodeOptions::new().with_max_allocation(3 * 24 + 2 * 400);
+        assert!(matches!(
+            decode::<Vec<Vec<u32>>>(&encoded, options)
+                .expect_err("The allocation budget is too small")
+                .root(),
+            DecodeError::AllocationLimit { .. }
+        ));
+
+        let encoded = Some(Some(Some(1u8)))
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        let options = DecodeOptions::new().with_max_depth(2);
+        assert!(matches!(
+            decode::<Option<Option<Option<u8>>>>(&encoded, options)
+                .expect_err("The value is nested too deeply"),
+            DecodeError::DepthLimit { limit: 2, .. }
+        ));
+        let options = DecodeOptions::new().with_max_depth(3);
+        assert_eq!(
+            decode::<Option<Option<Option<u8>>>>(&encoded, options)
+                .expect("The value is nested exactly as deep as allowed"),
+            Some(Some(Some(1)))
+        );
+
+        // A bogus length with too few bytes behind it fails with an IO error
+        // instead of allocating the whole array upfront.
+        let mut encoded = (u16::MAX as u32).to_be_bytes().to_vec();
+        encoded.extend([1u8; 10]);
+        assert!(matches!(
+            decode::<Vec<u64>>(&encoded, DecodeOptions::new()).expect_err("The input is truncated"),
+            DecodeError::IO { offset: 14, .. }
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "tokio")]
+    fn async_decode_wrong_encoded_size() {
+        /// A value which claims to be encoded as `SIZE` bytes, but is encoded
+        /// as a single `u16`.
+        #[derive(Debug)]
+        struct Misreported<const SIZE: usize>(#[allow(dead_code)] u16);
+
+        impl<const SIZE: usize> Decode for Misreported<SIZE> {
+            const ENCODED_SIZE: Option<usize> = Some(SIZE);
+
+            fn decode(reader: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+                Ok(Self(u16::decode(reader)?))
+            }
+        }
+
+        impl<const SIZE: usize> AsyncDecode for Misreported<SIZE> {
+            fn decode_async_with<'a, R: AsyncReader + 'a>(
+                _: &'a mut AsyncDecoder<R>,
+            ) -> DecodeFuture<'a, Self> {
+                unreachable!("Values with a fixed size are decoded using their Decode impl")
+            }
+        }
+
+        let encoded = [0u8, 1, 2, 3, 4];
+
+        assert!(matches!(
+            block_on(<(u8, Misreported<4>)>::decode_tokio(
+                &mut encoded.as_slice()
+            )),
+            Err(DecodeError::TrailingBytes {
+                remaining: 2,
+                offset: 3
+            })
+        ));
+        assert!(matches!(
+            block_on(<(u8, Misreported<1>)>::decode_tokio(
+                &mut encoded.as_slice()
+            )),
+            Err(DecodeError::IO { offset: 2, .. })
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "tokio")]
+    fn async_decode_errors() {
+        // The invalid character lands in the second chunk of the elements.
+        let characters = vec!['a'; 5000];
+        let mut encoded = characters
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        let offset = 4 + 3000 * 4;
+        encoded[offset..offset + 4].copy_from_slice(&0xD800u32.to_be_bytes());
+
+        let error = block_on(Vec::<char>::decode_tokio(&mut encoded.as_slice()))
+            .expect_err("The character is invalid");
+        let path = error.path().expect("The error contains the index");
+        assert_eq!(path.segments(), &[PathSegment::Index(3000)]);
+        assert!(matches!(
+            error.root(),
+            DecodeError::InvalidChar {
+                value: 0xD800,
+                offset: 12004
+            }
+        ));
+
+        let encoded = vec![(2u8, 0u8), (1, 0)]
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        assert!(matches!(
+            block_on(BTreeMap::<u8, u8>::decode_tokio(&mut encoded.as_slice()))
+                .expect_err("The keys are not sorted"),
+            DecodeError::UnsortedKey { index: 1, .. }
+        ));
+
+        let encoded = String::from("Hello")
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        assert!(matches!(
+            block_on(String::decode_tokio(&mut &encoded[..7])).expect_err("The input is truncated"),
+            DecodeError::IO { offset: 7, .. }
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "futures-io")]
+    fn async_futures_io() {
+        let value = (vec![String::from("Hello"); 3], [7u32; 4], Some(1.5f32));
+        let mut encoded = Vec::new();
+        block_on(value.encode_futures(&mut encoded)).expect("We can always encode into to a Vec");
+
+        let mut reader = TricklingReader {
+            bytes: &encoded,
+            ready: false,
+        };
+        let decoded = block_on(<(Vec<String>, [u32; 4], Option<f32>)>::decode_futures(
+            &mut reader,
+        ))
+        .expect("Decoding a freshly encoded value always works");
+        assert_eq!(value, decoded);
+
+        let mut decoder = AsyncDecoder::new(FuturesReader(TricklingReader {
+            bytes: &encoded[..20],
+            ready: false,
+        }));
+        block_on(decoder.decode::<(Vec<String>, [u32; 4], Option<f32>)>())
+            .expect_err("The input is truncated");
+        assert_eq!(decoder.position(), 20);
+    }
+
+    #[test]
+    #[cfg(all(feature = "derive", feature = "tokio"))]
+    fn derive_async_decode() {
+        #[derive(Debug, Decode, AsyncDecode, Encode, AsyncEncode, PartialEq, Eq)]
+        struct OneTimeKey {
+            key_id: u32,
+            public_key: [u8; 32],
+        }
+
+        #[derive(Debug, Decode, AsyncDecode, Encode, AsyncEncode, PartialEq, Eq)]
+        struct Account {
+            name: String,
+            one_time_keys: Vec<OneTimeKey>,
+        }
+
+        #[derive(Debug, Decode, AsyncDecode, Encode, AsyncEncode, PartialEq, Eq)]
+        struct Wrapper<T>(u8, T);
+
+        #[derive(Debug, Decode, AsyncDecode, Encode, AsyncEncode, PartialEq, Eq)]
+        enum Pickle {
+            Account(Account),
+            Wrapper(Wrapper<String>),
+        }
+
+        let pickle = Pickle::Account(Account {
+            name: String::from("alice"),
+            one_time_keys: (0..20)
+                .map(|key_id| OneTimeKey {
+                    key_id,
+                    public_key: [key_id as u8; 32],
+                })
+                .collect(),
+        });
+        let mut encoded = pickle
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        let mut async_encoded = Vec::new();
+        block_on(pickle.encode_tokio(&mut async_encoded))
+            .expect("We can always encode into to a Vec");
+        assert_eq!(async_encoded, encoded);
+
+        let decoded = block_on(Pickle::decode_tokio(&mut encoded.as_slice()))
+            .expect("Decoding a freshly encoded value always works");
+        assert_eq!(pickle, decoded);
+
+        let pickle = Pickle::Wrapper(Wrapper(1, String::from("bob")));
+        let wrapped = pickle
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        let decoded = block_on(Pickle::decode_tokio(&mut wrapped.as_slice()))
+            .expect("Decoding a freshly encoded value always works");
+        assert_eq!(pickle, decoded);
+
+        // Replace the name with invalid UTF-8.
+        encoded[1 + 4] = 0xFF;
+        let error = block_on(Pickle::decode_tokio(&mut encoded.as_slice()))
+            .expect_err("The name is invalid UTF-8");
+        assert_eq!(
+            error.path().map(ToString::to_string).as_deref(),
+            Some("Pickle::Account.name")
+        );
+        assert!(matches!(
+            error.root(),
+            DecodeError::InvalidUtf8 { offset: 1, .. }
+        ));
+
+        encoded[0] = 2;
+        assert!(matches!(
+            block_on(Pickle::decode_tokio(&mut encoded.as_slice()))
+                .expect_err("The variant is unknown"),
+            DecodeError::UnknownEnumVariant {
+                type_name: "Pickle",
+                variant: 2,
+                offset: 0,
+                ..
+            }
+        ));
+
+        let options = DecodeOptions::new().with_max_depth(2);
+        let mut decoder = AsyncDecoder::with_options(TokioReader(wrapped.as_slice()), options);
+        assert!(matches!(
+            block_on(decoder.decode::<Pickle>())
+                .expect_err("The value is nested too deeply")
+                .root(),
+            DecodeError::DepthLimit { limit: 2, .. }
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "derive")]
+    fn derive() {
+        #[derive(Clone, Encode, Decode, PartialEq, Debug)]
+        struct Foo {
+            thing: [u8; 32],
+            #[secret]
+            another: Box<[u8; 64]>,
+        }
+
+        let foo = Foo {
+            thing: [1u8; 32],
+            another: Box::new([2u8; 64]),
+        };
+
+        encode_cycle!(foo.clone() => Foo);
+
+        #[derive(Clone, Encode, Decode, PartialEq, Debug)]
+        struct Bar([u8; 32]);
+
+        let bar = Bar([1u8; 32]);
+        encode_cycle!(bar.clone() => Bar);
+
+        #[derive(Clone, Encode, Decode, PartialEq, Debug)]
+        struct Pointers {
+            boxed: Box<Bar>,
+            shared: Arc<Bar>,
+            bytes: Cow<'static, [u8]>,
+        }
+
+        let pointers = Pointers {
+            boxed: Box::new(bar.clone()),
+            shared: Arc::new(bar.clone()),
+            bytes: Cow::Borrowed(&[1, 2, 3]),
+        };
+        encode_cycle!(pointers => Pointers);
+
+        #[derive(Encode, Decode, PartialEq, Debug)]
+        enum Something {
+            Foo(Foo),
+            Bar(Bar),
+        }
+
+        let something = Something::Foo(foo);
+        encode_cycle!(something => Something);
+
+        let something = Something::Bar(bar);
+        encode_cycle!(something => Something);
+
+        #[derive(Clone, Encode, Decode, PartialEq, Debug)]
+        struct ChainKeys {
+            keys: [Bar; 3],
+            indices: [u32; 3],
+        }
+
+        let chain_keys = ChainKeys {
+            keys: [Bar([1u8; 32]), Bar([2u8; 32]), Bar([3u8; 32])],
+            indices: [1, 2, 3],
+        };
+        encode_cycle!(chain_keys => ChainKeys);
+
+        #[derive(Encode, DecodeBorrowed, PartialEq, Debug)]
+        struct BorrowedKeys<'a> {
+            public_key: &'a [u8; 32],
+            name: &'a str,
+            signatures: Vec<u8>,
+        }
+
+        #[derive(Encode, DecodeBorrowed, PartialEq, Debug)]
+        struct BorrowedTuple<'a, T>(&'a [u8], T);
+
+        #[derive(Encode, DecodeBorrowed, PartialEq, Debug)]
+        enum BorrowedEnum<'a, 'b> {
+            Keys(BorrowedKeys<'a>),
+            Tuple(BorrowedTuple<'b, u32>),
+        }
+
+        let keys = BorrowedEnum::Keys(BorrowedKeys {
+            public_key: &[1u8; 32],
+            name: "Alice",
+            signatures: vec![1, 2, 3],
+        });
+        let encoded = keys
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        let decoded = BorrowedEnum::decode_from_borrowed_slice(&encoded)
+            .expect("Decoding a freshly encoded value always works");
+        assert_eq!(keys, decoded);
+
+        let tuple = BorrowedEnum::Tuple(BorrowedTuple(&[1, 2, 3], 10));
+        let encoded = tuple
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        let decoded = BorrowedEnum::decode_from_borrowed_slice(&encoded)
+            .expect("Decoding a freshly encoded value always works");
+        assert_eq!(tuple, decoded);
+
+        let encoded = keys
+            .encode_to_vec()
+            .expect("We can always encode into to a Vec");
+        let options = DecodeOptions::new().with_max_allocation(2);
+        assert!(matches!(
+            Decoder::with_options(encoded.as_slice(), options)
+                .decode_borrowed::<BorrowedEnum<'_, '_>>()
+                .expect_err("The signatures exceed the allocation limit")
+                .root(),
+            DecodeError::AllocationLimit { limit: 2, .. }
+        ));
+
+        let options = DecodeOptions::new().with_max_depth(1);
+        assert!(matches!(
+            Decoder::with_options(encoded.as_slice(), options)
+                .decode_borrowed::<BorrowedEnum<'_, '_>>()
+                .expect_err("The value is nested too deeply")
+                .root(),
+            DecodeError::DepthLimit { limit: 1, .. }
+        ));
+
+        assert!(matches!(
+            BorrowedEnum::decode_from_borrowed_slice(&[2, 0, 0, 0, 0])
+                .expect_err("The variant is unknown"),
+            DecodeError::UnknownEnumVariant {
+                variant: 2,
+                offset: 0,
+                ..
+            }
+        ));
     }
 }
@@ -0,0 +1,230 @@
+// Copyright 2022 Damir Jelić
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    io::{ErrorKind, Write},
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use crate::{
+    async_io::{lock, poll_now, SecretBuffer},
+    AsyncEncode, AsyncEncoder, AsyncWriter, EncodeError, EncodeFuture,
+};
+
+/// The number of encoded bytes a [`PullEncoder`] buffers by default.
+const DEFAULT_CAPACITY: usize = 8 * 1024;
+
+/// The result of writing the output of a [`PullEncoder`] to a writer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EncodeProgress {
+    /// The writer returned an error of the kind `WouldBlock`, the rest of the
+    /// value is written once [`PullEncoder::write_to()`] is called again.
+    WouldBlock,
+    /// The whole value has been written, this many bytes in total.
+    Done(usize),
+}
+
+/// An encoder which produces the encoded value in pieces, for writers which
+/// can't accept all of it at once, without doing any IO itself.
+///
+/// The value is encoded into a buffer of a bounded size, encoding is paused
+/// while the buffer is full and resumes once its bytes have been taken out.
+/// It uses the [`AsyncEncode`] implementation of the encoded type, which can
+/// be derived.
+///
+/// ```
+/// use matrix_pickle::{Encode, EncodeProgress, PullEncoder};
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let value = vec![String::from("Hello"); 1000];
+///
+/// let mut encoder = PullEncoder::with_capacity(&value, 64);
+/// let mut socket = Vec::new();
+///
+/// assert_eq!(encoder.write_to(&mut socket)?, EncodeProgress::Done(socket.len()));
+/// assert_eq!(socket, value.encode_to_vec()?);
+/// # Ok(())
+/// # }
+/// ```
+pub struct PullEncoder<'a> {
+    output: Arc<Mutex<Output>>,
+    value: Option<EncodeFuture<'a>>,
+    written: usize,
+}
+
+impl<'a> PullEncoder<'a> {
+    /// Create a new `PullEncoder` for the given value, buffering up to 8 KiB
+    /// of the encoded value.
+    pub fn new<T: AsyncEncode + ?Sized>(value: &'a T) -> Self {
+        Self::with_capacity(value, DEFAULT_CAPACITY)
+    }
+
+    /// Create a new `PullEncoder` for the given value, buffering up to
+    /// `capacity` bytes of the encoded value.
+    pub fn with_capacity<T: AsyncEncode + ?Sized>(value: &'a T, capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        let output = Arc::new(Mutex::new(Output {
+            bytes: SecretBuffer::with_capacity(capacity),
+            capacity,
+        }));
+
+        let writer = BufferWriter(output.clone());
+        let value: EncodeFuture<'a> =
+            Box::pin(async move { AsyncEncoder::new(writer).encode(value).await });
+
+        Self {
+            output,
+            value: Some(value),
+            written: 0,
+        }
+    }
+
+    /// Take the next bytes of the encoded value, filling as much of the given
+    /// buffer as possible and returning the number of bytes put into it.
+    ///
+    /// Once the whole value has been taken out, zero is returned.
+    ///
+    /// Once an error has been returned, the encoded value is incomplete and
+    /// the encoder shouldn't be used any further.
+    pub fn pull(&mut self, buf: &mut [u8]) -> Result<usize, EncodeError> {
+        let mut filled = 0;
+
+        loop {
+            {
+                let mut output = lock(&self.output);
+                let count = output.bytes.len().min(buf.len() - filled);
+
+                buf[filled..filled + count].copy_from_slice(&output.bytes[..count]);
+                output.bytes.consume(count);
+
+                filled += count;
+            }
+
+            if filled == buf.len() || !self.resume()? {
+                break;
+            }
+        }
+
+        self.written += filled;
+
+        Ok(filled)
+    }
+
+    /// Write the encoded value to the given writer, until the writer would
+    /// block or the whole value has been written.
+    ///
+    /// Bytes which the writer didn't accept stay buffered, calling this again
+    /// continues where the previous call stopped. Short writes and
+    /// interrupted writes are retried.
+    ///
+    /// Once an error has been returned, the encoded value is incomplete and
+    /// the encoder shouldn't be used any further.
+    pub fn write_to(&mut self, writer: &mut impl Write) -> Result<EncodeProgress, EncodeError> {
+        loop {
+            let mut output = lock(&self.output);
+
+            if output.bytes.is_empty() {
+                // The writer needs to lock the output to encode more of the
+                // value.
+                drop(output);
+
+                if !self.resume()? {
+                    return Ok(EncodeProgress::Done(self.written));
+                }
+
+                continue;
+            }
+
+            match writer.write(&output.bytes) {
+                Ok(0) => return Err(std::io::Error::from(ErrorKind::WriteZero).into()),
+                Ok(written) => {
+                    output.bytes.consume(written);
+                    self.written += written;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    return Ok(EncodeProgress::WouldBlock)
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Has the whole encoded value been taken out of the encoder.
+    pub fn is_done(&self) -> bool {
+        self.value.is_none() && lock(&self.output).bytes.is_empty()
+    }
+
+    /// Continue encoding the value until the buffer is full, returning
+    /// whether there are any bytes left to take out.
+    fn resume(&mut self) -> Result<bool, EncodeError> {
+        let Some(value) = &mut self.value else {
+            return Ok(false);
+        };
+
+        // The writer never registers the waker, we poll again once the
+        // buffer has been emptied.
+        match poll_now(value.as_mut()) {
+            Poll::Ready(result) => {
+                self.value = None;
+                result?;
+
+                Ok(!lock(&self.output).bytes.is_empty())
+            }
+            Poll::Pending => Ok(true),
+        }
+    }
+}
+
+impl std::fmt::Debug for PullEncoder<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PullEncoder")
+            .field("buffered", &lock(&self.output).bytes.len())
+            .field("written", &self.written)
+            .field("encoding", &self.value.is_some())
+            .finish()
+    }
+}
+
+/// The encoded bytes which haven't been taken out of a [`PullEncoder`] yet.
+///
+/// The encoded value may contain secrets, the buffer never grows beyond its
+/// capacity and zeroizes the bytes once they have been taken out.
+#[derive(Debug)]
+struct Output {
+    bytes: SecretBuffer,
+    /// How many bytes may be buffered before encoding pauses.
+    capacity: usize,
+}
+
+/// An [`AsyncWriter`] which writes into the buffer of a [`PullEncoder`], and
+/// is pending while the buffer is full.
+struct BufferWriter(Arc<Mutex<Output>>);
+
+impl AsyncWriter for BufferWriter {
+    fn poll_write(&mut self, _: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let mut output = lock(&self.0);
+        let space = output.capacity.saturating_sub(output.bytes.len());
+
+        if space == 0 && !buf.is_empty() {
+            Poll::Pending
+        } else {
+            let written = space.min(buf.len());
+            output.bytes.extend_from_slice(&buf[..written]);
+
+            Poll::Ready(Ok(written))
+        }
+    }
+}
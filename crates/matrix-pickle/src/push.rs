@@ -0,0 +1,169 @@
+// Copyright 2022 Damir Jelić
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use zeroize::Zeroizing;
+
+use crate::{
+    async_io::{lock, poll_now, SecretBuffer},
+    AsyncDecode, AsyncDecoder, AsyncReader, DecodeError, DecodeFuture, DecodeOptions,
+};
+
+/// The result of pushing a chunk of input into a [`PushDecoder`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeProgress<T> {
+    /// The value isn't complete yet, at least this many more bytes are needed
+    /// before decoding can make progress.
+    NeedMoreBytes(usize),
+    /// The value has been decoded.
+    Done(T),
+}
+
+/// A decoder which is fed the input in chunks, for transports which deliver
+/// the data in pieces, without doing any IO itself.
+///
+/// The decoder keeps the values it has decoded so far between chunks, the
+/// input is never parsed more than once. It uses the [`AsyncDecode`]
+/// implementation of the decoded type, which can be derived.
+///
+/// Once a value is done, the bytes pushed after it are kept and become the
+/// start of the next value. The byte offsets of errors are relative to the
+/// start of the value that failed to decode.
+///
+/// The pushed input may contain secrets, the decoder zeroizes the bytes it has
+/// consumed as well as its buffer once it's dropped.
+///
+/// ```
+/// use matrix_pickle::{DecodeProgress, Encode, PushDecoder};
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let encoded = (5u32, String::from("Hello")).encode_to_vec()?;
+/// let mut decoder = PushDecoder::<(u32, String)>::new();
+///
+/// assert_eq!(decoder.push(&encoded[..3])?, DecodeProgress::NeedMoreBytes(1));
+/// assert_eq!(decoder.push(&encoded[3..10])?, DecodeProgress::NeedMoreBytes(3));
+/// assert_eq!(
+///     decoder.push(&encoded[10..])?,
+///     DecodeProgress::Done((5, String::from("Hello")))
+/// );
+/// # Ok(())
+/// # }
+/// ```
+pub struct PushDecoder<T> {
+    input: Arc<Mutex<Input>>,
+    options: DecodeOptions,
+    value: Option<DecodeFuture<'static, T>>,
+}
+
+impl<T: AsyncDecode + 'static> PushDecoder<T> {
+    /// Create a new `PushDecoder` using the default [`DecodeOptions`].
+    pub fn new() -> Self {
+        Self::with_options(DecodeOptions::default())
+    }
+
+    /// Create a new `PushDecoder` which enforces the given [`DecodeOptions`]
+    /// for every decoded value.
+    pub fn with_options(options: DecodeOptions) -> Self {
+        Self {
+            input: Arc::default(),
+            options,
+            value: None,
+        }
+    }
+
+    /// Push the next chunk of input into the decoder, and continue decoding
+    /// the current value.
+    ///
+    /// An empty chunk can be pushed to start decoding the next value from
+    /// bytes that were left over after the previous one.
+    ///
+    /// Once an error has been returned, the position in the input is unknown
+    /// and the decoder shouldn't be used any further.
+    pub fn push(&mut self, chunk: &[u8]) -> Result<DecodeProgress<T>, DecodeError> {
+        lock(&self.input).bytes.extend_from_slice(chunk);
+
+        let input = &self.input;
+        let options = self.options;
+
+        let value = self.value.get_or_insert_with(|| {
+            let reader = ChunkReader(input.clone());
+            Box::pin(async move { AsyncDecoder::with_options(reader, options).decode().await })
+        });
+
+        // The reader never registers the waker, we poll again once more
+        // input arrives.
+        match poll_now(value.as_mut()) {
+            Poll::Ready(result) => {
+                self.value = None;
+                result.map(DecodeProgress::Done)
+            }
+            Poll::Pending => Ok(DecodeProgress::NeedMoreBytes(lock(&self.input).wanted)),
+        }
+    }
+
+    /// Get back the bytes which were pushed into the decoder, but haven't
+    /// been decoded yet.
+    pub fn into_buffered(self) -> Zeroizing<Vec<u8>> {
+        std::mem::take(&mut lock(&self.input).bytes).into_unconsumed()
+    }
+}
+
+impl<T: AsyncDecode + 'static> Default for PushDecoder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> std::fmt::Debug for PushDecoder<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PushDecoder")
+            .field("buffered", &lock(&self.input).bytes.len())
+            .field("options", &self.options)
+            .field("decoding", &self.value.is_some())
+            .finish()
+    }
+}
+
+/// The input which was pushed into a [`PushDecoder`] but hasn't been read yet.
+#[derive(Debug, Default)]
+struct Input {
+    bytes: SecretBuffer,
+    /// How many bytes the last read which ran out of input asked for.
+    wanted: usize,
+}
+
+/// An [`AsyncReader`] which reads the input pushed into a [`PushDecoder`],
+/// and is pending once it runs out of it.
+struct ChunkReader(Arc<Mutex<Input>>);
+
+impl AsyncReader for ChunkReader {
+    fn poll_read(&mut self, _: &mut Context<'_>, buf: &mut [u8]) -> Poll<std::io::Result<usize>> {
+        let mut input = lock(&self.0);
+
+        if input.bytes.is_empty() && !buf.is_empty() {
+            input.wanted = buf.len();
+            Poll::Pending
+        } else {
+            let read = input.bytes.len().min(buf.len());
+            buf[..read].copy_from_slice(&input.bytes[..read]);
+            input.bytes.consume(read);
+
+            Poll::Ready(Ok(read))
+        }
+    }
+}